@@ -3,15 +3,19 @@ let
   cfg = config.services.fw-lights;
   description = "Framework LED Matrix daemon";
   socket_path = "/run/fw-lights/fw-lights.sock";
+  # created by `StateDirectory`
+  state_dir = "/var/lib/fw-lights";
 
-  configToml = (pkgs.formats.toml {}).generate "fw-lights.toml" (cfg // {
+  # the daemon rejects unknown keys
+  configToml = (pkgs.formats.toml {}).generate "fw-lights.toml" (removeAttrs cfg [ "enable" "persist_definitions" ] // {
     inherit socket_path;
+  } // pkgs.lib.optionalAttrs cfg.persist_definitions {
+    inherit state_dir;
   });
 
-  nc = "${pkgs.netcat}/bin/nc";
   sendChargerEvent = pkgs.writeShellScript "fw-lights-send-charger-event" ''
     set -e
-    echo charger | ${nc} -UN ${socket_path}
+    ${fw-lights}/bin/fw-lights ctl --socket ${socket_path} charger
   '';
 in
 {
@@ -19,8 +23,20 @@ in
     enable = mkEnableOption description;
 
     displays = mkOption {
-      description = "Paths to serial devices corresponding to displays";
-      type = types.attrsOf types.str;
+      description = "Paths to serial devices corresponding to displays, or attrsets with `path` and `brightness`";
+      type = types.attrsOf (types.either types.str (types.submodule {
+        options = {
+          path = mkOption {
+            description = "Path to the serial device";
+            type = types.str;
+          };
+          brightness = mkOption {
+            description = "Display brightness";
+            type = types.ints.u8;
+            default = 255;
+          };
+        };
+      }));
       example = {
         left = "/dev/ttyACM1";
         right = "/dev/ttyACM0";
@@ -62,6 +78,11 @@ in
               };
             });
           };
+          progress = mkOption {
+            description = "Named progress bar styles, `default` is used when no style is requested";
+            type = types.attrs;
+            default = {};
+          };
         };
       };
       default = {};
@@ -73,6 +94,18 @@ in
       type = types.attrs;
       default = {};
     };
+
+    animation_dirs = mkOption {
+      description = "Directories whose animation files are registered under their file stem, as paths or attrsets with `path` and `manifest`";
+      type = types.listOf (types.either types.str types.attrs);
+      default = [];
+    };
+
+    persist_definitions = mkOption {
+      description = "Keep animations defined over the control socket across restarts";
+      type = types.bool;
+      default = false;
+    };
   };
 
   config = {
@@ -85,9 +118,12 @@ in
         User = "root";
         Group = "fw-lights";
         UMask = "0007";
-        ExecStart = "${fw-lights}/bin/fw-lights ${configToml}";
+        ExecStart = "${fw-lights}/bin/fw-lights daemon --config ${configToml}";
+        ExecReload = "${pkgs.coreutils}/bin/kill -HUP $MAINPID";
         # TODO: do some hardening? is there even a point?
         RuntimeDirectory = "fw-lights";
+      } // pkgs.lib.optionalAttrs cfg.persist_definitions {
+        StateDirectory = "fw-lights";
       };
     };
     services.udev.extraRules = if cfg.builtin.charger != null then ''
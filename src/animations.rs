@@ -5,8 +5,14 @@ use crate::proto::BwFrame;
 pub mod builder;
 
 // specific animations
+pub mod automaton;
 pub mod file;
+pub mod image;
+pub mod progress;
+pub mod script;
+pub mod shader;
 pub mod spread;
+pub mod stream;
 
 pub type Animation = Box<dyn Iterator<Item = Frame> + Send + Sync>;
 
@@ -36,7 +42,7 @@ pub trait IsFrame: Default {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct GrayFrame(pub [[u8; 34]; 9]);
 
 impl GrayFrame {
@@ -80,14 +86,54 @@ impl Default for GrayFrame {
     }
 }
 
+/// Grayscale pixels plus black and white ones, whose value is only known once the
+/// brightness black and white frames are drawn at is, e.g. tweens from them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BlendFrame {
+    pub gray: GrayFrame,
+    /// How lit each black and white pixel is, out of 255.
+    pub bw: GrayFrame,
+}
+
+impl BlendFrame {
+    pub fn to_gray(&self, bw_brightness: u8) -> GrayFrame {
+        let mut result = self.gray.clone();
+        for x in 0..9 {
+            for y in 0..34 {
+                // cast is safe, as the product is at most 255 * 255
+                let bw = (u16::from(self.bw.0[x][y]) * u16::from(bw_brightness) / 255) as u8;
+                result.0[x][y] = result.0[x][y].saturating_add(bw);
+            }
+        }
+        result
+    }
+
+    /// Pixels to send to the display when drawn on its own: the display scales them by its
+    /// brightness, just like the lit pixels of black and white frames.
+    pub fn to_drawn(&self) -> GrayFrame {
+        self.to_gray(255)
+    }
+}
+
 #[allow(clippy::large_enum_variant)] // maybe actually box? dunno
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum FrameData {
     Gray(GrayFrame),
     Bw(BwFrame),
+    Blend(Box<BlendFrame>),
 }
 
-#[derive(Clone)]
+impl FrameData {
+    pub fn into_gray(self, bw_brightness: u8) -> GrayFrame {
+        match self {
+            Self::Gray(frame) => frame,
+            Self::Bw(frame) => GrayFrame::from_bw(frame, bw_brightness),
+            Self::Blend(frame) => frame.to_gray(bw_brightness),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct Frame {
     pub data: FrameData,
     pub min_duration: Duration,
@@ -107,13 +153,11 @@ impl Frame {
 
         let data = match (self.data, upper.data) {
             (FrameData::Bw(lower), FrameData::Bw(upper)) => FrameData::Bw(lower.merge(upper)),
-            (FrameData::Gray(lower), FrameData::Gray(upper)) => FrameData::Gray(lower.merge(upper)),
-            (FrameData::Bw(lower), FrameData::Gray(upper)) => {
-                FrameData::Gray(GrayFrame::from_bw(lower, bw_brightness).merge(upper))
-            }
-            (FrameData::Gray(lower), FrameData::Bw(upper)) => {
-                FrameData::Gray(lower.merge(GrayFrame::from_bw(upper, bw_brightness)))
-            }
+            (lower, upper) => FrameData::Gray(
+                lower
+                    .into_gray(bw_brightness)
+                    .merge(upper.into_gray(bw_brightness)),
+            ),
         };
 
         Self {
@@ -133,6 +177,10 @@ impl Frame {
         let data = match data {
             FrameData::Gray(frame) => FrameData::Gray(frame.offset(offset)),
             FrameData::Bw(frame) => FrameData::Bw(frame.offset(offset)),
+            FrameData::Blend(frame) => FrameData::Blend(Box::new(BlendFrame {
+                gray: frame.gray.offset(offset),
+                bw: frame.bw.offset(offset),
+            })),
         };
         Self {
             data,
@@ -0,0 +1,295 @@
+use std::{str::FromStr, time::Duration};
+
+use eyre::{bail, eyre};
+use serde::Deserialize;
+
+use crate::{
+    animations::{Frame, FrameData, GrayFrame, IsFrame as _, file::FileAnimation},
+    config::{self, AutomatonSeed, Edges},
+    rng::Rng,
+};
+
+type Grid = [[bool; 34]; 9];
+
+/// Life-like rule in the `B3/S23` notation.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Rule {
+    birth: [bool; 9],
+    survive: [bool; 9],
+}
+
+impl FromStr for Rule {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        let mut birth = None;
+        let mut survive = None;
+        for part in s.split('/') {
+            let mut counts = [false; 9];
+            let mut chars = part.chars();
+            let target = match chars.next().map(|c| c.to_ascii_uppercase()) {
+                Some('B') => &mut birth,
+                Some('S') => &mut survive,
+                _ => bail!("bad rule `{s}`: every part should start with `B` or `S`"),
+            };
+            for c in chars {
+                let Some(count) = c.to_digit(10).filter(|&count| count <= 8) else {
+                    bail!("bad rule `{s}`: wrong neighbour count `{c}`");
+                };
+                counts[count as usize] = true;
+            }
+            if target.replace(counts).is_some() {
+                bail!("bad rule `{s}`: duplicate part");
+            }
+        }
+
+        Ok(Self {
+            birth: birth.ok_or_else(|| eyre!("bad rule `{s}`: no `B` part"))?,
+            survive: survive.ok_or_else(|| eyre!("bad rule `{s}`: no `S` part"))?,
+        })
+    }
+}
+
+impl TryFrom<String> for Rule {
+    type Error = eyre::Report;
+
+    fn try_from(value: String) -> eyre::Result<Self> {
+        value.parse()
+    }
+}
+
+#[derive(Clone)]
+pub struct Automaton {
+    grid: Grid,
+    previous: Option<Grid>,
+    rule: Rule,
+    edges: Edges,
+    brightness: u8,
+    frame_duration: Duration,
+    generation: usize,
+    max_generations: Option<usize>,
+    stopped: bool,
+}
+
+impl Automaton {
+    pub fn from_config(config: &config::AutomatonAnimation) -> eyre::Result<Self> {
+        let mut grid = [[false; 34]; 9];
+        match &config.seed {
+            AutomatonSeed::File { path, frame } => {
+                let animation = FileAnimation::load(path)?;
+                let Some(frame) = animation.frames.get(*frame) else {
+                    bail!("animation file `{}` has no frame {frame}", path.display());
+                };
+                for x in 0..9 {
+                    for y in 0..34 {
+                        grid[x as usize][y as usize] = match &frame.data {
+                            FrameData::Gray(frame) => frame.get(x, y) != 0,
+                            FrameData::Bw(frame) => frame.get(x, y),
+                            FrameData::Blend(frame) => frame.to_gray(255).get(x, y) != 0,
+                        };
+                    }
+                }
+            }
+            AutomatonSeed::Random { seed, density } => {
+                let mut rng = Rng::new(*seed);
+                for cell in grid.iter_mut().flatten() {
+                    *cell = rng.next_f32() < *density;
+                }
+            }
+        }
+
+        Ok(Self {
+            grid,
+            previous: None,
+            rule: config.rule.clone(),
+            edges: config.edges,
+            brightness: config.brightness,
+            frame_duration: config.frame_duration,
+            generation: 0,
+            max_generations: config.max_generations,
+            stopped: false,
+        })
+    }
+
+    fn neighbours(&self, x: usize, y: usize) -> usize {
+        let mut count = 0;
+        for dx in [-1, 0, 1] {
+            for dy in [-1, 0, 1] {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                let (nx, ny) = match self.edges {
+                    Edges::Toroidal => (nx.rem_euclid(9), ny.rem_euclid(34)),
+                    Edges::Bounded if (0..9).contains(&nx) && (0..34).contains(&ny) => (nx, ny),
+                    Edges::Bounded => continue,
+                };
+                count += usize::from(self.grid[nx as usize][ny as usize]);
+            }
+        }
+        count
+    }
+
+    fn step(&self) -> Grid {
+        let mut next = [[false; 34]; 9];
+        for (x, column) in next.iter_mut().enumerate() {
+            for (y, cell) in column.iter_mut().enumerate() {
+                let neighbours = self.neighbours(x, y);
+                *cell = if self.grid[x][y] {
+                    self.rule.survive[neighbours]
+                } else {
+                    self.rule.birth[neighbours]
+                };
+            }
+        }
+        next
+    }
+
+    fn to_frame(&self) -> GrayFrame {
+        let mut frame = GrayFrame::default();
+        for x in 0..9 {
+            for y in 0..34 {
+                if self.grid[x][y] {
+                    frame.0[x][y] = self.brightness;
+                }
+            }
+        }
+        frame
+    }
+}
+
+impl Iterator for Automaton {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stopped {
+            return None;
+        }
+
+        let frame = Frame {
+            data: FrameData::Gray(self.to_frame()),
+            min_duration: self.frame_duration,
+            fullscreen: false,
+        };
+
+        self.generation += 1;
+        let next = self.step();
+        // stagnation: everything died, still life or a period-2 oscillator
+        let stagnated = next == self.grid
+            || self.previous == Some(next)
+            || next.iter().flatten().all(|&alive| !alive);
+        let exhausted = self
+            .max_generations
+            .is_some_and(|max| self.generation >= max);
+        if stagnated || exhausted {
+            self.stopped = true;
+        }
+        self.previous = Some(std::mem::replace(&mut self.grid, next));
+
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn automaton(
+        cells: &[(usize, usize)],
+        edges: Edges,
+        max_generations: Option<usize>,
+    ) -> Automaton {
+        let mut grid = [[false; 34]; 9];
+        for &(x, y) in cells {
+            grid[x][y] = true;
+        }
+        Automaton {
+            grid,
+            previous: None,
+            rule: "B3/S23".parse().unwrap(),
+            edges,
+            brightness: 255,
+            frame_duration: Duration::from_millis(100),
+            generation: 0,
+            max_generations,
+            stopped: false,
+        }
+    }
+
+    fn alive(grid: &Grid) -> Vec<(usize, usize)> {
+        (0..9)
+            .flat_map(|x| (0..34).map(move |y| (x, y)))
+            .filter(|&(x, y)| grid[x][y])
+            .collect()
+    }
+
+    #[test]
+    fn rules() {
+        let rule: Rule = "B3/S23".parse().unwrap();
+        assert_eq!(rule.birth.map(u8::from), [0, 0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(rule.survive.map(u8::from), [0, 0, 1, 1, 0, 0, 0, 0, 0]);
+        let rule: Rule = "s/b36".parse().unwrap();
+        assert_eq!(rule.birth.map(u8::from), [0, 0, 0, 1, 0, 0, 1, 0, 0]);
+        assert_eq!(rule.survive, [false; 9]);
+
+        let error = |s: &str| s.parse::<Rule>().unwrap_err().to_string();
+        assert_eq!(error("B3"), "bad rule `B3`: no `S` part");
+        assert_eq!(error("S23"), "bad rule `S23`: no `B` part");
+        assert_eq!(
+            error("B9/S23"),
+            "bad rule `B9/S23`: wrong neighbour count `9`"
+        );
+        assert_eq!(error("B3/S2/S3"), "bad rule `B3/S2/S3`: duplicate part");
+        assert_eq!(
+            error("B3/X23"),
+            "bad rule `B3/X23`: every part should start with `B` or `S`"
+        );
+        assert_eq!(
+            error(""),
+            "bad rule ``: every part should start with `B` or `S`"
+        );
+    }
+
+    #[test]
+    fn blinker() {
+        for edges in [Edges::Toroidal, Edges::Bounded] {
+            let automaton = automaton(&[(3, 10), (4, 10), (5, 10)], edges, None);
+            assert_eq!(alive(&automaton.step()), [(4, 9), (4, 10), (4, 11)]);
+        }
+
+        // on the top edge, the torus wraps the blinker around while bounded edges cut it
+        let wrapped = automaton(&[(3, 0), (4, 0), (5, 0)], Edges::Toroidal, None);
+        assert_eq!(alive(&wrapped.step()), [(4, 0), (4, 1), (4, 33)]);
+        let cut = automaton(&[(3, 0), (4, 0), (5, 0)], Edges::Bounded, None);
+        assert_eq!(alive(&cut.step()), [(4, 0), (4, 1)]);
+    }
+
+    #[test]
+    fn stops_when_stagnating() {
+        // a period-2 oscillator stops once it's back to the first frame
+        let blinker = automaton(&[(3, 10), (4, 10), (5, 10)], Edges::Toroidal, None);
+        assert_eq!(blinker.count(), 2);
+        let block = automaton(&[(1, 1), (1, 2), (2, 1), (2, 2)], Edges::Bounded, None);
+        assert_eq!(block.count(), 1);
+        let lonely = automaton(&[(4, 17)], Edges::Bounded, None);
+        assert_eq!(lonely.count(), 1);
+    }
+
+    #[test]
+    fn stops_after_max_generations() {
+        // a glider on a torus never stagnates
+        let glider = [(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)];
+        let frames: Vec<_> = automaton(&glider, Edges::Toroidal, Some(50)).collect();
+        assert_eq!(frames.len(), 50);
+        assert!(
+            frames
+                .iter()
+                .all(|frame| frame.min_duration == Duration::from_millis(100))
+        );
+        assert_eq!(
+            automaton(&glider, Edges::Toroidal, None).take(500).count(),
+            500
+        );
+    }
+}
@@ -1,44 +1,99 @@
-use std::{fs, str::FromStr as _};
-
-use eyre::WrapErr as _;
-
 use crate::{
-    animations::{self, Animation},
+    animations::{self, Animation, spread::Seeds},
     config::{AnimationConfig, BuiltinAnimation},
 };
 
-type BuilderFn = Box<dyn Fn(Option<i8>) -> Animation + Send + Sync>;
+type BuilderFn = Box<dyn Fn(&PlayArgs) -> Animation + Send + Sync>;
+
+/// Per-play parameters passed from the control socket.
+#[derive(Clone, Default)]
+pub struct PlayArgs {
+    pub offset: Option<i8>,
+    /// Replaces configured seeds; only makes sense for seedable animations.
+    pub seeds: Option<Seeds>,
+}
 
 pub struct AnimationBuilder {
-    build: Box<dyn Fn(Option<i8>) -> Animation + Send + Sync>,
+    build: BuilderFn,
+    seedable: bool,
 }
 
 impl AnimationBuilder {
     pub fn new(config: AnimationConfig) -> eyre::Result<Self> {
+        let mut seedable = false;
         let build = match config {
             AnimationConfig::Builtin(builtin) => match builtin {
-                BuiltinAnimation::Spread(config) => Box::new(move |offset: Option<i8>| {
-                    animations::spread::from_config_at(config.clone(), offset.unwrap_or(0))
-                }) as BuilderFn,
+                BuiltinAnimation::Spread(config) => {
+                    seedable = true;
+                    Box::new(move |args: &PlayArgs| {
+                        animations::spread::from_config_at(
+                            config.clone(),
+                            args.offset.unwrap_or(0),
+                            args.seeds.as_ref(),
+                        )
+                    }) as BuilderFn
+                }
+                BuiltinAnimation::Automaton(config) => {
+                    let automaton = animations::automaton::Automaton::from_config(&config)?;
+                    Box::new(move |args: &PlayArgs| {
+                        let offset = args.offset.unwrap_or(0);
+                        Box::new(automaton.clone().map(move |frame| frame.offset(offset)))
+                            as Animation
+                    }) as _
+                }
+                BuiltinAnimation::Shader(config) => {
+                    let shader = animations::shader::Shader::from_config(&config);
+                    Box::new(move |args: &PlayArgs| {
+                        let offset = args.offset.unwrap_or(0);
+                        Box::new(shader.clone().map(move |frame| frame.offset(offset))) as Animation
+                    }) as _
+                }
             },
             AnimationConfig::File(file) => {
-                let path = &file.path;
-                let raw = fs::read_to_string(path).wrap_err_with(|| {
-                    format!("failed to read animation file `{}`", path.display())
-                })?;
-                let builder = animations::file::FileAnimation::from_str(&raw)?;
-                Box::new(move |offset| builder.at(offset)) as _
+                let animation = animations::file::FileAnimation::load(&file.path)?;
+                return Ok(Self::file(animation));
+            }
+            AnimationConfig::Image(config) => {
+                let builder = animations::image::load(&config)?;
+                Box::new(move |args: &PlayArgs| builder.at(args.offset)) as _
+            }
+            AnimationConfig::Script(config) => {
+                let builder = animations::script::ScriptBuilder::new(config)?;
+                Box::new(move |args: &PlayArgs| {
+                    let offset = args.offset.unwrap_or(0);
+                    Box::new(builder.build().map(move |frame| frame.offset(offset))) as Animation
+                }) as _
             }
         };
 
-        Ok(Self { build })
+        Ok(Self { build, seedable })
+    }
+
+    /// Builder for an already parsed animation file.
+    pub fn file(animation: animations::file::FileAnimation) -> Self {
+        Self {
+            build: Box::new(move |args: &PlayArgs| animation.at(args.offset)),
+            seedable: false,
+        }
+    }
+
+    /// Whether this animation accepts [`PlayArgs::seeds`].
+    pub fn is_seedable(&self) -> bool {
+        self.seedable
     }
 
     pub fn build(&self) -> Animation {
-        (self.build)(None)
+        self.with(&PlayArgs::default())
     }
 
     pub fn at(&self, offset: i8) -> Animation {
-        (self.build)(Some(offset))
+        self.with(&PlayArgs {
+            offset: Some(offset),
+            ..PlayArgs::default()
+        })
+    }
+
+    pub fn with(&self, args: &PlayArgs) -> Animation {
+        (self.build)(args)
     }
 }
@@ -1,29 +1,100 @@
-use std::{iter, str::FromStr, time::Duration};
+use std::{
+    collections::HashMap,
+    fs,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+    time::Duration,
+};
 
-use eyre::{bail, ensure};
-use itertools::Itertools as _;
-use serde::Deserialize;
+use eyre::{WrapErr as _, bail};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     animations::{Animation, Frame, FrameData, GrayFrame, IsFrame as _},
     proto::BwFrame,
 };
 
-#[derive(Clone, Default, Deserialize)]
-#[serde(default)]
+mod diagnostic;
+mod sprite;
+mod tween;
+mod writer;
+
+pub use diagnostic::{Diagnostic, ParseError};
+pub use sprite::{Blend, Placement, Sprite};
+pub use tween::{Easing, Tween};
+pub use writer::write_frames;
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct FileOptions {
     pub default_offset: i8,
     #[serde(with = "humantime_serde")]
     pub min_duration: Duration,
     pub fullscreen: bool,
+    /// Other animation files, referenced as `@alias` (all frames) or `@alias.name`.
+    /// Relative paths are resolved from the including file.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub include: HashMap<String, PathBuf>,
+    /// Characters of compact grayscale rows from darkest to brightest, spread evenly
+    /// over 0-255. Defaults to hex digits, so `0` is off and `f` is full brightness.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
 }
 
+const DEFAULT_PALETTE: &str = "0123456789abcdef";
+
+/// Most frames a file may expand to, as nested `repeat`s and `goto`s grow exponentially.
+const MAX_FRAMES: usize = 100_000;
+
 pub struct FileAnimation {
     pub frames: Vec<Frame>,
     pub default_offset: i8,
+    /// Frames marked with `name` option, available to files including this one.
+    pub named: HashMap<String, Frame>,
+    pub sprites: HashMap<String, Sprite>,
+    /// Whether names, labels, includes, sprites, tweens, compact rows or a palette were
+    /// used, so [`Self::write`] loses structure or syntax.
+    pub has_directives: bool,
 }
 
 impl FileAnimation {
+    /// Reads and parses a file, resolving includes relative to it.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        Self::load_nested(path, &mut Vec::new())
+    }
+
+    fn load_nested(path: &Path, stack: &mut Vec<PathBuf>) -> eyre::Result<Self> {
+        let raw = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read animation file `{}`", path.display()))?;
+        let canonical = path.canonicalize()?;
+        if stack.contains(&canonical) {
+            bail!("animation file `{}` includes itself", path.display());
+        }
+
+        stack.push(canonical);
+        let result = Self::parse(&raw, Some(path.parent().unwrap_or(Path::new("."))), stack)
+            .wrap_err_with(|| format!("failed to parse animation file `{}`", path.display()));
+        stack.pop();
+        result
+    }
+
+    pub fn from_frames(frames: Vec<Frame>) -> Self {
+        Self {
+            frames,
+            default_offset: 0,
+            named: HashMap::new(),
+            sprites: HashMap::new(),
+            has_directives: false,
+        }
+    }
+
+    /// Serializes this animation back into the canonical file format.
+    pub fn write(&self) -> String {
+        write_frames(&self.frames, self.default_offset)
+    }
+
     pub fn at(&self, offset: Option<i8>) -> Animation {
         let offset = offset.unwrap_or(self.default_offset);
         Box::new(
@@ -35,46 +106,168 @@ impl FileAnimation {
     }
 }
 
+/// Parses the rows of a single frame, without a header or options; used for frames
+/// streamed over the control socket.
+pub fn parse_frame(rows: &str) -> Result<Frame, ParseError> {
+    let lines = rows.lines().enumerate().map(|(n, line)| (n + 1, line));
+    let palette = DEFAULT_PALETTE.chars().collect();
+    let mut parser = Parser::new(lines, palette, HashMap::new(), Vec::new());
+    let mut frames = Vec::new();
+    parser.parse_frame(&FrameOptions::default(), &mut frames);
+    if let Some(&(n, raw_line)) = parser.lines.peek() {
+        parser.error(n, raw_line, raw_line.trim(), "expected a single frame");
+    }
+    if frames.len() != 1 && parser.diagnostics.is_empty() {
+        parser.diagnostics.push(Diagnostic::in_text(
+            rows,
+            1,
+            None,
+            "expected a single frame",
+        ));
+    }
+    if !parser.diagnostics.is_empty() {
+        return Err(ParseError::new(parser.diagnostics, rows));
+    }
+    Ok(frames.remove(0))
+}
+
 impl FromStr for FileAnimation {
     type Err = eyre::Report;
 
+    /// Parses an animation which isn't in a file, so includes are refused.
     fn from_str(s: &str) -> eyre::Result<Self> {
+        Self::parse(s, None, &mut Vec::new())
+    }
+}
+
+impl FileAnimation {
+    /// Includes are resolved relative to `base_dir`, and refused without one.
+    fn parse(s: &str, base_dir: Option<&Path>, stack: &mut Vec<PathBuf>) -> eyre::Result<Self> {
         let Some(header_delim) = s.find("\n---\n") else {
             bail!("animation file doesn't contain `---` line");
         };
 
-        let options: FileOptions = toml::from_str(&s[..header_delim])?;
+        let mut diagnostics = Vec::new();
+        let header = &s[..header_delim];
+        let options: FileOptions = toml::from_str(header).unwrap_or_else(|err| {
+            diagnostics.push(Diagnostic::in_text(header, 1, err.span(), err.message()));
+            FileOptions::default()
+        });
+        let mut includes = HashMap::new();
+        for (alias, path) in &options.include {
+            // keys are searched after the table name, so that other options don't match
+            let span = header.find("include").and_then(|table| {
+                let span = diagnostic::key_span(&header[table..], alias)?;
+                Some(span.start + table..span.end + table)
+            });
+            let Some(base_dir) = base_dir else {
+                diagnostics.push(Diagnostic::in_text(
+                    header,
+                    1,
+                    span,
+                    format!("can't include `{alias}`, includes only work in animation files"),
+                ));
+                continue;
+            };
+            match Self::load_nested(&base_dir.join(path), stack) {
+                Ok(animation) => {
+                    includes.insert(alias.clone(), animation);
+                }
+                Err(err) => {
+                    let mut diagnostic = Diagnostic::in_text(header, 1, span, "");
+                    if let Some(nested) = err.downcast_ref::<ParseError>() {
+                        diagnostic.message = format!("failed to include `{alias}`");
+                        for problem in &nested.diagnostics {
+                            diagnostic.notes.push(format!(
+                                "in `{}`, line {}, column {}: {}",
+                                path.display(),
+                                problem.line,
+                                problem.column,
+                                problem.message,
+                            ));
+                            diagnostic.notes.extend(problem.notes.iter().cloned());
+                        }
+                    } else {
+                        diagnostic.message = format!("failed to include `{alias}`: {err:#}");
+                    }
+                    diagnostics.push(diagnostic);
+                }
+            }
+        }
+        let mut palette: Vec<char> = options
+            .palette
+            .as_deref()
+            .unwrap_or(DEFAULT_PALETTE)
+            .chars()
+            .collect();
+        if let Err(message) = check_palette(&palette) {
+            diagnostics.push(Diagnostic::in_text(header, 1, None, message));
+            palette = DEFAULT_PALETTE.chars().collect();
+        }
         let default_frame_options = FrameOptions {
-            repeat: None,
             fullscreen: Some(options.fullscreen),
             min_duration: Some(options.min_duration),
+            ..FrameOptions::default()
         };
         let data = &s[header_delim + 5..];
-        let first_line_idx = s[..header_delim].bytes().filter(|&b| b == b'\n').count() + 3;
-        let mut lines = data
+        let first_line_idx = header.bytes().filter(|&b| b == b'\n').count() + 3;
+        let lines = data
             .lines()
             .enumerate()
             .map(|(n, line)| (n + first_line_idx, line));
+        let mut parser = Parser::new(lines, palette, includes, diagnostics);
         let mut frames = Vec::new();
-        while parse_frame(&mut lines, &default_frame_options, &mut frames)? {}
+        while parser.parse_frame(&default_frame_options, &mut frames) {}
 
+        if !parser.diagnostics.is_empty() {
+            return Err(ParseError::new(parser.diagnostics, s).into());
+        }
         Ok(Self {
             frames,
             default_offset: options.default_offset,
+            named: parser.named,
+            sprites: parser.sprites,
+            has_directives: parser.has_directives || options.palette.is_some(),
         })
     }
 }
 
-#[derive(Clone, Default, Deserialize)]
-#[serde(default)]
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct FrameOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub repeat: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fullscreen: Option<bool>,
-    #[serde(with = "humantime_serde::option")]
+    #[serde(
+        with = "humantime_serde::option",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub min_duration: Option<Duration>,
+    /// Makes the frame available as `@name` later in the file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Only define the named frame without playing it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hidden: Option<bool>,
+    /// Fades from this frame into the next one through generated frames, or moves the
+    /// sprites both of them place.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tween: Option<Tween>,
 }
 
 impl FrameOptions {
+    /// Rejects options which parse but can't be meant.
+    fn check(&self) -> Result<(), &'static str> {
+        if self.repeat == Some(0) {
+            return Err("`repeat` must be at least 1");
+        }
+        if self.tween.as_ref().is_some_and(|tween| tween.frames == 0) {
+            return Err("`tween` needs at least 1 frame");
+        }
+        Ok(())
+    }
+
     pub fn merge_with(&mut self, other: &FrameOptions) {
         if other.repeat.is_some() {
             self.repeat = other.repeat;
@@ -85,6 +278,25 @@ impl FrameOptions {
         if other.min_duration.is_some() {
             self.min_duration = other.min_duration;
         }
+        if other.name.is_some() {
+            self.name.clone_from(&other.name);
+        }
+        if other.hidden.is_some() {
+            self.hidden = other.hidden;
+        }
+        if other.tween.is_some() {
+            self.tween.clone_from(&other.tween);
+        }
+    }
+
+    /// Applies explicitly set options to an already built frame.
+    fn override_frame(&self, frame: &mut Frame) {
+        if let Some(min_duration) = self.min_duration {
+            frame.min_duration = min_duration;
+        }
+        if let Some(fullscreen) = self.fullscreen {
+            frame.fullscreen = fullscreen;
+        }
     }
 
     pub fn make_bw(self, frame: BwFrame) -> Frame {
@@ -104,100 +316,1192 @@ impl FrameOptions {
     }
 }
 
-fn parse_frame<'a>(
-    lines: &mut impl Iterator<Item = (usize, &'a str)>,
-    default_options: &FrameOptions,
-    into: &mut Vec<Frame>,
-) -> eyre::Result<bool> {
-    let mut options = default_options.clone();
-    loop {
-        let Some((n, line)) = lines.next() else {
-            return Ok(false);
+/// Syntax of pixel rows, detected from the first row of each frame or sprite.
+///
+/// Rows starting with `.` or `#` are black and white unless the palette has that character,
+/// rows with whitespace are hex, and anything else is compact, so compact rows can't contain
+/// whitespace. A lone hex number is thus read as compact pixels: `ff` is two of them, and
+/// one-column grayscale sprites are written with a single palette character per row, like `f`.
+/// Hex rows of single palette characters that would read differently as compact pixels, like
+/// `0 8 f`, are ambiguous and rejected.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RowKind {
+    /// `.` and `#`.
+    Bw,
+    /// Whitespace-separated hex numbers.
+    Hex,
+    /// A palette character per pixel.
+    Compact,
+}
+
+impl RowKind {
+    fn of(line: &str, palette: &[char]) -> Self {
+        let first = line.chars().next().unwrap_or_default();
+        if (first == '.' || first == '#') && !palette.contains(&first) {
+            Self::Bw
+        } else if line.contains(char::is_whitespace) {
+            Self::Hex
+        } else {
+            Self::Compact
+        }
+    }
+}
+
+fn check_palette(palette: &[char]) -> Result<(), String> {
+    if palette.len() < 2 {
+        return Err("palette needs at least two characters".to_owned());
+    }
+    for (idx, &c) in palette.iter().enumerate() {
+        if c.is_whitespace() || c == '{' || c == '@' {
+            return Err(format!("palette can't contain {c:?}"));
+        }
+        if palette[..idx].contains(&c) {
+            return Err(format!("palette contains {c:?} twice"));
+        }
+    }
+    Ok(())
+}
+
+struct Parser<'a, I: Iterator<Item = (usize, &'a str)>> {
+    lines: Peekable<I>,
+    diagnostics: Vec<Diagnostic>,
+    named: HashMap<String, Frame>,
+    sprites: HashMap<String, Sprite>,
+    // label name to index of the first frame after it
+    labels: HashMap<String, usize>,
+    // tween of the last played frame, applied once the next one is known
+    pending_tween: Option<Tween>,
+    // sprites of played frames drawn by `place`, by frame index, for tweening positions
+    placements: HashMap<usize, Rc<[Placement]>>,
+    palette: Vec<char>,
+    includes: HashMap<String, FileAnimation>,
+    has_directives: bool,
+}
+
+impl<'a, I: Iterator<Item = (usize, &'a str)>> Parser<'a, I> {
+    fn new(
+        lines: I,
+        palette: Vec<char>,
+        includes: HashMap<String, FileAnimation>,
+        diagnostics: Vec<Diagnostic>,
+    ) -> Self {
+        Self {
+            lines: lines.peekable(),
+            diagnostics,
+            named: HashMap::new(),
+            sprites: HashMap::new(),
+            labels: HashMap::new(),
+            pending_tween: None,
+            placements: HashMap::new(),
+            palette,
+            has_directives: !includes.is_empty(),
+            includes,
+        }
+    }
+
+    fn error(&mut self, n: usize, raw_line: &str, part: &str, message: impl Into<String>) {
+        self.diagnostics
+            .push(Diagnostic::at(n, raw_line, part, message));
+    }
+
+    /// Parses options lines and a single frame, reference or directive;
+    /// returns `false` on end of input.
+    fn parse_frame(&mut self, default_options: &FrameOptions, into: &mut Vec<Frame>) -> bool {
+        // options set in this frame, as opposed to file-wide defaults
+        let mut explicit = FrameOptions::default();
+        loop {
+            let Some(&(n, raw_line)) = self.lines.peek() else {
+                return false;
+            };
+            let line = raw_line.trim();
+            if line.is_empty() {
+                self.lines.next();
+                continue;
+            }
+
+            // options line
+            if line.as_bytes()[0] == b'{' {
+                self.lines.next();
+                match FrameOptions::deserialize(toml::de::ValueDeserializer::new(line)) {
+                    Ok(frame_options) => {
+                        if let Err(message) = frame_options.check() {
+                            self.error(n, raw_line, line, message);
+                        }
+                        explicit.merge_with(&frame_options);
+                    }
+                    Err(err) => {
+                        let column = diagnostic::column_of(raw_line, line);
+                        let span = err
+                            .span()
+                            .or_else(|| diagnostic::unknown_key_span(line, err.message()));
+                        let mut diagnostic =
+                            Diagnostic::in_text(line, n, span.clone(), err.message());
+                        diagnostic.column += column - 1;
+                        if span.is_none() {
+                            diagnostic.width = line.len();
+                        }
+                        self.diagnostics.push(diagnostic);
+                    }
+                }
+                continue;
+            }
+
+            if let Some(label) = line.strip_prefix("label ") {
+                self.lines.next();
+                self.has_directives = true;
+                self.labels.insert(label.trim().to_owned(), into.len());
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix("sprite ") {
+                self.lines.next();
+                self.has_directives = true;
+                self.parse_sprite(name.trim());
+                continue;
+            }
+
+            if let Some(goto) = line.strip_prefix("goto ") {
+                self.lines.next();
+                self.has_directives = true;
+                self.parse_goto(n, raw_line, goto, into);
+                return true;
+            }
+
+            let mut options = default_options.clone();
+            options.merge_with(&explicit);
+            let repeat = options.repeat.unwrap_or(1);
+            let name = options.name.clone();
+            let hidden = options.hidden.unwrap_or(false);
+            let tween = options.tween.clone();
+            if tween.is_some() {
+                self.has_directives = true;
+            }
+            if hidden && name.is_none() {
+                self.error(n, raw_line, line, "hidden frame needs a `name` to be used");
+            }
+
+            let mut placed = None;
+            let frames = if let Some(reference) = line.strip_prefix('@') {
+                self.lines.next();
+                self.has_directives = true;
+                let Some(mut frames) = self.resolve(n, raw_line, reference) else {
+                    return true;
+                };
+                for frame in &mut frames {
+                    explicit.override_frame(frame);
+                }
+                frames
+            } else if line.starts_with("place ") {
+                self.has_directives = true;
+                let (frame, placements) = self.parse_placements(options);
+                placed = Some(Rc::from(placements));
+                vec![frame]
+            } else {
+                match RowKind::of(line, &self.palette) {
+                    RowKind::Bw => vec![self.parse_bw(options)],
+                    RowKind::Hex => vec![self.parse_gray(options, RowKind::Hex)],
+                    RowKind::Compact => {
+                        // the writer only knows hex rows
+                        self.has_directives = true;
+                        vec![self.parse_gray(options, RowKind::Compact)]
+                    }
+                }
+            };
+
+            if let Some(name) = name {
+                self.has_directives = true;
+                match frames.as_slice() {
+                    [frame] => {
+                        self.named.insert(name, frame.clone());
+                    }
+                    _ => self.error(n, raw_line, line, "only a single frame can be named"),
+                }
+            }
+            if !hidden && !frames.is_empty() {
+                let added = frames
+                    .len()
+                    .checked_mul(repeat)
+                    .and_then(|added| added.checked_add(self.pending_tween_frames()));
+                if !self.within_limit(n, raw_line, line, into.len(), added) {
+                    return true;
+                }
+                self.play_tween((n, raw_line, line), &frames[0], placed.clone(), into);
+                for _ in 0..repeat {
+                    if let Some(placed) = &placed {
+                        self.placements.insert(into.len(), placed.clone());
+                    }
+                    into.extend(frames.iter().cloned());
+                }
+                self.pending_tween = tween;
+            }
+            return true;
+        }
+    }
+
+    /// `goto <label> <count> times`: plays frames since the label `count` more times.
+    fn parse_goto(&mut self, n: usize, raw_line: &str, goto: &str, into: &mut Vec<Frame>) {
+        let (label, count) = match goto.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+            [label, count, "times"] => (label, count),
+            _ => {
+                self.error(n, raw_line, goto, "expected `goto <label> <count> times`");
+                return;
+            }
+        };
+        let Ok(count) = count.parse::<usize>() else {
+            self.error(n, raw_line, goto, format!("bad repeat count `{count}`"));
+            return;
+        };
+        let Some(&start) = self.labels.get(label) else {
+            self.error(n, raw_line, goto, format!("unknown label `{label}`"));
+            return;
+        };
+
+        let section = into[start..].to_vec();
+        let Some(first) = section.first() else {
+            return;
         };
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
+        let added = section
+            .len()
+            .checked_add(self.pending_tween_frames())
+            .and_then(|per_repetition| per_repetition.checked_mul(count));
+        if !self.within_limit(n, raw_line, goto, into.len(), added) {
+            return;
+        }
+        // a tween on the last frame leads back to the start of every repetition
+        let tween = self.pending_tween.clone();
+        for _ in 0..count {
+            let placed = self.placements.get(&start).cloned();
+            self.play_tween((n, raw_line, goto), first, placed, into);
+            for idx in 0..section.len() {
+                if let Some(placed) = self.placements.get(&(start + idx)).cloned() {
+                    self.placements.insert(into.len() + idx, placed);
+                }
+            }
+            into.extend(section.iter().cloned());
+            self.pending_tween.clone_from(&tween);
         }
+    }
 
-        // options line
-        if line.as_bytes()[0] == b'{' {
-            let frame_options = FrameOptions::deserialize(toml::de::ValueDeserializer::new(line))?;
-            options.merge_with(&frame_options);
-            continue;
+    fn pending_tween_frames(&self) -> usize {
+        self.pending_tween.as_ref().map_or(0, |tween| tween.frames)
+    }
+
+    /// Checks that `added` more frames (`None` on overflow) keep the animation within
+    /// [`MAX_FRAMES`], reporting an error otherwise.
+    fn within_limit(
+        &mut self,
+        n: usize,
+        raw_line: &str,
+        part: &str,
+        current: usize,
+        added: Option<usize>,
+    ) -> bool {
+        let total = added.and_then(|added| added.checked_add(current));
+        if total.is_some_and(|total| total <= MAX_FRAMES) {
+            return true;
         }
+        self.error(
+            n,
+            raw_line,
+            part,
+            format!("animation would have more than {MAX_FRAMES} frames"),
+        );
+        false
+    }
 
-        let mut lines = iter::once((n, line)).chain(lines);
+    /// Inserts frames of the pending tween leading to `next`, which `placed` draws if it
+    /// comes from `place` lines; errors are reported at `part` of line `n`.
+    fn play_tween(
+        &mut self,
+        (n, raw_line, part): (usize, &str, &str),
+        next: &Frame,
+        placed: Option<Rc<[Placement]>>,
+        into: &mut Vec<Frame>,
+    ) {
+        let Some(tween) = self.pending_tween.take() else {
+            return;
+        };
+        let Some(last) = into.last() else {
+            return;
+        };
+        if !tween.positions {
+            into.extend(tween.between(last, next));
+            return;
+        }
 
-        let repeat = options.repeat;
-        let frame = if line.as_bytes()[0] == b'.' || line.as_bytes()[0] == b'#' {
-            parse_bw(&mut lines, options)?
-        } else {
-            parse_gray(&mut lines, options)?
+        let moved = self
+            .placements
+            .get(&(into.len() - 1))
+            .zip(placed)
+            .and_then(|(from, to)| tween.moving(from, &to));
+        let Some(moved) = moved else {
+            self.error(
+                n,
+                raw_line,
+                part,
+                "tweening positions needs both keyframes to place the same sprites",
+            );
+            return;
         };
+        let options = FrameOptions {
+            min_duration: Some(last.min_duration),
+            fullscreen: Some(last.fullscreen),
+            ..FrameOptions::default()
+        };
+        into.extend(
+            moved
+                .iter()
+                .map(|placements| render_placements(placements, options.clone())),
+        );
+    }
 
-        for _ in 0..repeat.unwrap_or(1) {
-            into.push(frame.clone());
+    /// Resolves `name`, `alias.name` or `alias` after `@`.
+    fn resolve(&mut self, n: usize, raw_line: &str, reference: &str) -> Option<Vec<Frame>> {
+        let resolved = match reference.split_once('.') {
+            Some((alias, name)) => self
+                .includes
+                .get(alias)
+                .and_then(|include| include.named.get(name))
+                .map(|frame| vec![frame.clone()]),
+            None => self
+                .named
+                .get(reference)
+                .map(|frame| vec![frame.clone()])
+                .or_else(|| {
+                    self.includes
+                        .get(reference)
+                        .map(|include| include.frames.clone())
+                }),
+        };
+        if resolved.is_none() {
+            self.error(
+                n,
+                raw_line,
+                reference,
+                format!("unknown frame or include `{reference}`"),
+            );
         }
-        return Ok(true);
+        resolved
+    }
+
+    /// Calls `parse_row` for every row of a frame, checking that there are exactly 34 of them.
+    fn parse_rows(&mut self, mut parse_row: impl FnMut(&mut Self, usize, &'a str, u8)) {
+        let mut first = None;
+        let mut y = 0;
+        while let Some((n, raw_line)) = self.lines.next() {
+            let line = raw_line.trim();
+            if line.is_empty() {
+                break;
+            }
+            first.get_or_insert((n, raw_line, line));
+
+            if y == 34 {
+                self.error(n, raw_line, line, "too many rows in frame, expected 34");
+            }
+            if y < 34 {
+                // cast is safe because of the check above
+                parse_row(self, n, raw_line, y as u8);
+            }
+            y += 1;
+        }
+
+        if let Some((n, raw_line, line)) = first
+            && y < 34
+        {
+            self.error(
+                n,
+                raw_line,
+                line,
+                format!("frame has only {y} rows, expected 34"),
+            );
+        }
+    }
+
+    fn parse_bw(&mut self, options: FrameOptions) -> Frame {
+        let mut frame = BwFrame::default();
+        self.parse_rows(|this, n, raw_line, y| {
+            let Some(pixels) = this.bw_row(n, raw_line) else {
+                return;
+            };
+            if this.check_width(n, raw_line, pixels.len(), 9) {
+                for (x, pixel) in pixels.into_iter().enumerate() {
+                    // cast is safe because it's an array index
+                    frame.set(x as u8, y, pixel);
+                }
+            }
+        });
+        options.make_bw(frame)
+    }
+
+    fn parse_gray(&mut self, options: FrameOptions, kind: RowKind) -> Frame {
+        let mut frame = GrayFrame::default();
+        self.parse_rows(|this, n, raw_line, y| {
+            let pixels = match kind {
+                RowKind::Compact => this.compact_row(n, raw_line),
+                _ => this.gray_row(n, raw_line),
+            };
+            let Some(pixels) = pixels else {
+                return;
+            };
+            if this.check_width(n, raw_line, pixels.len(), 9) {
+                for (x, pixel) in pixels.into_iter().enumerate() {
+                    frame.0[x][y as usize] = pixel;
+                }
+            }
+        });
+        options.make_gray(frame)
+    }
+
+    fn check_width(&mut self, n: usize, raw_line: &str, width: usize, expected: usize) -> bool {
+        if width != expected {
+            self.error(
+                n,
+                raw_line,
+                raw_line.trim(),
+                format!("wrong row length: expected {expected} pixels, got {width}"),
+            );
+        }
+        width == expected
     }
-}
 
-fn parse_bw<'a>(
-    lines: &mut impl Iterator<Item = (usize, &'a str)>,
-    options: FrameOptions,
-) -> Result<Frame, eyre::Error> {
-    let mut frame = BwFrame::default();
-    for (y, (n, line)) in lines.enumerate() {
-        let line = line.trim();
-        if line.is_empty() {
-            break;
-        }
-
-        ensure!(y < 34, "line {n}: too many lines in frame");
-        let Some(pixels) = line
-            .bytes()
-            .filter(|c| !c.is_ascii_whitespace())
-            .collect_array::<9>()
-        else {
-            bail!("line {n}: wrong frame line length");
-        };
-        for (x, pixel) in pixels.into_iter().enumerate() {
-            ensure!(
-                pixel == b'.' || pixel == b'#',
-                "line {n}: wrong pixel '{}': should be '.' or '#'",
-                pixel as char,
+    /// Parses a row of `.` and `#`; returns `None` if any pixel is wrong.
+    fn bw_row(&mut self, n: usize, raw_line: &str) -> Option<Vec<bool>> {
+        let mut ok = true;
+        let mut pixels = Vec::with_capacity(9);
+        for (idx, pixel) in raw_line.char_indices().filter(|(_, c)| !c.is_whitespace()) {
+            if pixel != '.' && pixel != '#' {
+                let part = &raw_line[idx..idx + pixel.len_utf8()];
+                self.error(
+                    n,
+                    raw_line,
+                    part,
+                    format!("wrong pixel '{pixel}': should be '.' or '#'"),
+                );
+                ok = false;
+            }
+            pixels.push(pixel == '#');
+        }
+        ok.then_some(pixels)
+    }
+
+    /// Parses a row of whitespace-separated hex numbers; returns `None` if any pixel is wrong
+    /// or the row could also be compact pixels separated by spaces.
+    fn gray_row(&mut self, n: usize, raw_line: &str) -> Option<Vec<u8>> {
+        let as_compact: Option<Vec<u8>> = raw_line
+            .split_ascii_whitespace()
+            .map(|pixel| {
+                let mut chars = pixel.chars();
+                let level = self.level(chars.next()?)?;
+                chars.next().is_none().then_some(level)
+            })
+            .collect();
+        if let Some(as_compact) = as_compact
+            && raw_line
+                .split_ascii_whitespace()
+                .map(|pixel| u8::from_str_radix(pixel, 16).ok())
+                .ne(as_compact.into_iter().map(Some))
+        {
+            self.error(
+                n,
+                raw_line,
+                raw_line.trim(),
+                "ambiguous row: write hex pixels with two digits, like `0f`, \
+                 or compact pixels without spaces",
+            );
+            return None;
+        }
+
+        let mut ok = true;
+        let mut pixels = Vec::with_capacity(9);
+        for pixel in raw_line.split_ascii_whitespace() {
+            let Ok(value) = u8::from_str_radix(pixel, 16) else {
+                self.error(
+                    n,
+                    raw_line,
+                    pixel,
+                    format!("wrong pixel {pixel:?}: should be a hex number from 00 to ff"),
+                );
+                ok = false;
+                continue;
+            };
+            pixels.push(value);
+        }
+        ok.then_some(pixels)
+    }
+
+    /// Parses a row of palette characters; returns `None` if any pixel is wrong.
+    fn compact_row(&mut self, n: usize, raw_line: &str) -> Option<Vec<u8>> {
+        let line = raw_line.trim();
+        if let Some(idx) = line.find(char::is_whitespace) {
+            let end = line[idx..]
+                .find(|c: char| !c.is_whitespace())
+                .map_or(line.len(), |len| idx + len);
+            self.error(
+                n,
+                raw_line,
+                &line[idx..end],
+                "compact rows can't contain whitespace",
+            );
+            return None;
+        }
+
+        let mut ok = true;
+        let mut pixels = Vec::with_capacity(9);
+        for (idx, pixel) in raw_line.char_indices().filter(|(_, c)| !c.is_whitespace()) {
+            let Some(level) = self.level(pixel) else {
+                let part = &raw_line[idx..idx + pixel.len_utf8()];
+                let palette: String = self.palette.iter().collect();
+                self.error(
+                    n,
+                    raw_line,
+                    part,
+                    format!("wrong pixel '{pixel}': should be one of \"{palette}\""),
+                );
+                ok = false;
+                continue;
+            };
+            pixels.push(level);
+        }
+        ok.then_some(pixels)
+    }
+
+    /// Brightness of a palette character, spread evenly over 0-255.
+    fn level(&self, pixel: char) -> Option<u8> {
+        let max = self.palette.len() - 1;
+        let level = self.palette.iter().position(|&c| c == pixel)?;
+        // cast is safe because the result is at most 255
+        Some((level * 255 / max) as u8)
+    }
+
+    /// `sprite <name>` followed by rows of pixels of any size that fits the display.
+    fn parse_sprite(&mut self, name: &str) {
+        let mut rows: Vec<Vec<u8>> = Vec::new();
+        let mut kind = None;
+        let mut ok = true;
+        let mut first = None;
+        while let Some((n, raw_line)) = self.lines.next() {
+            let line = raw_line.trim();
+            if line.is_empty() {
+                break;
+            }
+            first.get_or_insert((n, raw_line, line));
+
+            let row_kind = RowKind::of(line, &self.palette);
+            if *kind.get_or_insert(row_kind) != row_kind {
+                self.error(n, raw_line, line, "sprite mixes different row syntaxes");
+                ok = false;
+                continue;
+            }
+            let row = match row_kind {
+                RowKind::Bw => self.bw_row(n, raw_line).map(|row| {
+                    row.into_iter()
+                        .map(|lit| if lit { 255 } else { 0 })
+                        .collect()
+                }),
+                RowKind::Hex => self.gray_row(n, raw_line),
+                RowKind::Compact => self.compact_row(n, raw_line),
+            };
+            let Some(row) = row else {
+                ok = false;
+                continue;
+            };
+            if let Some(width) = rows.first().map(Vec::len)
+                && !self.check_width(n, raw_line, row.len(), width)
+            {
+                ok = false;
+                continue;
+            }
+            rows.push(row);
+        }
+
+        let Some((n, raw_line, line)) = first else {
+            return;
+        };
+        let width = rows.first().map_or(0, Vec::len);
+        if rows.len() > 34 || width > 9 {
+            self.error(
+                n,
+                raw_line,
+                line,
+                format!(
+                    "sprite is {width}x{}, which is bigger than the 9x34 display",
+                    rows.len()
+                ),
+            );
+            ok = false;
+        }
+        if ok {
+            self.sprites.insert(
+                name.to_owned(),
+                Sprite {
+                    rows,
+                    bw: kind.is_none_or(|kind| kind == RowKind::Bw),
+                },
             );
-            // y cast is safe because of the ensure above
-            // x cast is safe because it's an array index
-            frame.set(x as u8, y as u8, pixel == b'#');
         }
     }
-    Ok(options.make_bw(frame))
+
+    /// Consecutive `place <sprite> at <x> <y> [<blend>]` lines, composed into a single frame.
+    fn parse_placements(&mut self, options: FrameOptions) -> (Frame, Vec<Placement>) {
+        let mut placements = Vec::new();
+        while let Some(&(n, raw_line)) = self.lines.peek() {
+            let Some(placement) = raw_line.trim().strip_prefix("place ") else {
+                break;
+            };
+            self.lines.next();
+
+            let words: Vec<_> = placement.split_ascii_whitespace().collect();
+            let (name, x, y, blend) = match words[..] {
+                [name, "at", x, y] => (name, x, y, None),
+                [name, "at", x, y, blend] => (name, x, y, Some(blend)),
+                _ => {
+                    self.error(
+                        n,
+                        raw_line,
+                        placement,
+                        "expected `place <sprite> at <x> <y> [<blend>]`",
+                    );
+                    continue;
+                }
+            };
+            let (Ok(x), Ok(y)) = (x.parse::<i16>(), y.parse::<i16>()) else {
+                self.error(n, raw_line, placement, "bad sprite coordinates");
+                continue;
+            };
+            let blend = match blend.map(Blend::from_str).transpose() {
+                Ok(blend) => blend.unwrap_or_default(),
+                Err(err) => {
+                    self.error(n, raw_line, placement, err.to_string());
+                    continue;
+                }
+            };
+            let sprite = match name.split_once('.') {
+                Some((alias, name)) => self
+                    .includes
+                    .get(alias)
+                    .and_then(|include| include.sprites.get(name)),
+                None => self.sprites.get(name),
+            };
+            let Some(sprite) = sprite else {
+                self.error(n, raw_line, name, format!("unknown sprite `{name}`"));
+                continue;
+            };
+
+            placements.push(Placement {
+                name: name.to_owned(),
+                sprite: sprite.clone(),
+                x,
+                y,
+                blend,
+            });
+        }
+
+        (render_placements(&placements, options), placements)
+    }
 }
 
-fn parse_gray<'a>(
-    lines: &mut impl Iterator<Item = (usize, &'a str)>,
-    options: FrameOptions,
-) -> Result<Frame, eyre::Error> {
+/// Draws sprites onto a blank frame, which stays black and white if every sprite is.
+fn render_placements(placements: &[Placement], options: FrameOptions) -> Frame {
     let mut frame = GrayFrame::default();
+    for placement in placements {
+        placement
+            .sprite
+            .draw(&mut frame, placement.x, placement.y, placement.blend);
+    }
 
-    for (y, (n, line)) in lines.enumerate() {
-        let line = line.trim();
-        if line.is_empty() {
-            break;
+    if placements.iter().all(|placement| placement.sprite.bw) {
+        let mut bw = BwFrame::default();
+        for x in 0..9 {
+            for y in 0..34 {
+                bw.set(x, y, frame.get(x, y) != 0);
+            }
         }
+        options.make_bw(bw)
+    } else {
+        options.make_gray(frame)
+    }
+}
 
-        ensure!(y < 34, "line {n}: too many lines in frame");
-        let Some(pixels) = line.split_ascii_whitespace().collect_array::<9>() else {
-            bail!("line {n}: wrong frame line length");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame of 34 identical rows, followed by a blank line.
+    fn frame(row: &str) -> String {
+        format!("{}\n", format!("{row}\n").repeat(34))
+    }
+
+    fn parse(s: &str) -> FileAnimation {
+        match s.parse() {
+            Ok(animation) => animation,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    #[test]
+    fn write_round_trips() {
+        let source = format!(
+            "min_duration = \"10ms\"\n---\n{}{}{{ min_duration = \"20ms\" }}\n{}{}",
+            frame("#........"),
+            frame("#........"),
+            frame("00 11 22 33 44 55 66 77 ff"),
+            frame(".#.#.#.#."),
+        );
+        let animation = parse(&source);
+        assert_eq!(animation.frames.len(), 4);
+        assert!(!animation.has_directives);
+
+        let written = animation.write();
+        assert!(written.contains("{ repeat = 2 }"));
+        assert!(written.contains("{ min_duration = \"20ms\" }"));
+        let reparsed = parse(&written);
+        assert!(reparsed.frames == animation.frames);
+        assert_eq!(reparsed.write(), written);
+    }
+
+    #[test]
+    fn directives_are_detected() {
+        let source = format!("\n---\n{{ name = \"a\" }}\n{}@a\n", frame("#........"));
+        let animation = parse(&source);
+        assert_eq!(animation.frames.len(), 2);
+        assert!(animation.has_directives);
+    }
+
+    fn errors(s: &str) -> Vec<String> {
+        let Err(err) = s.parse::<FileAnimation>() else {
+            panic!("parsing should fail");
         };
-        for (x, pixel) in pixels.into_iter().enumerate() {
-            let Ok(pixel) = u8::from_str_radix(pixel, 16) else {
-                bail!("line {n}: wrong pixel {pixel:?}");
-            };
-            frame.0[x][y] = pixel;
+        err.downcast_ref::<ParseError>()
+            .expect("should be a parse error")
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.clone())
+            .collect()
+    }
+
+    fn rendered(s: &str) -> String {
+        let Err(err) = s.parse::<FileAnimation>() else {
+            panic!("parsing should fail");
+        };
+        err.to_string()
+    }
+
+    #[test]
+    fn diagnostics_point_at_pixels() {
+        let source = format!(
+            "min_duration = \"10ms\"\n---\n{}  #...x....\n{}",
+            "#........\n".repeat(2),
+            "#........\n".repeat(31),
+        );
+        assert_eq!(
+            rendered(&source),
+            "1 error in animation file\n\n\
+             line 5, column 7: wrong pixel 'x': should be '.' or '#'\n  \
+               |\n\
+             5 |   #...x....\n  \
+               |       ^",
+        );
+    }
+
+    #[test]
+    fn diagnostics_for_short_frames() {
+        let source = format!("\n---\n{}\n", "#........\n".repeat(30));
+        assert_eq!(
+            rendered(&source),
+            "1 error in animation file\n\n\
+             line 3, column 1: frame has only 30 rows, expected 34\n  \
+               |\n\
+             3 | #........\n  \
+               | ^^^^^^^^^",
+        );
+    }
+
+    #[test]
+    fn diagnostics_for_unknown_options() {
+        assert_eq!(
+            rendered("speed = 2\n---\n"),
+            "1 error in animation file\n\n\
+             line 1, column 1: unknown field `speed`, expected one of `default_offset`, \
+             `min_duration`, `fullscreen`, `include`, `palette`\n  \
+               |\n\
+             1 | speed = 2\n  \
+               | ^^^^^",
+        );
+        let source = format!(
+            "\n---\n{{ repeat = 2, colour = 1 }}\n{}",
+            frame("#........")
+        );
+        assert_eq!(
+            rendered(&source),
+            "1 error in animation file\n\n\
+             line 3, column 15: unknown field `colour`, expected one of `repeat`, `fullscreen`, \
+             `min_duration`, `name`, `hidden`, `tween`\n  \
+               |\n\
+             3 | { repeat = 2, colour = 1 }\n  \
+               |               ^^^^^^",
+        );
+    }
+
+    #[test]
+    fn diagnostics_are_collected() {
+        let source = format!(
+            "\n---\n00 00 zz 00 00 00 00 00 00\n{}\n{}#.#\n{}\n{{ oops }}\n{}",
+            "00 00 00 00 00 00 00 00 00\n".repeat(33),
+            "#........\n".repeat(3),
+            "#........\n".repeat(30),
+            frame("#........"),
+        );
+        assert_eq!(
+            rendered(&source),
+            "3 errors in animation file\n\n\
+             line 3, column 7: wrong pixel \"zz\": should be a hex number from 00 to ff\n  \
+               |\n\
+             3 | 00 00 zz 00 00 00 00 00 00\n  \
+               |       ^^\n\n\
+             line 41, column 1: wrong row length: expected 9 pixels, got 3\n   \
+                |\n\
+             41 | #.#\n   \
+                | ^^^\n\n\
+             line 73, column 8: expected `.`, `=`\n   \
+                |\n\
+             73 | { oops }\n   \
+                |        ^",
+        );
+    }
+
+    #[test]
+    fn labels_and_goto() {
+        let source = format!(
+            "\n---\n{}label loop\n{}{}goto loop 2 times\n",
+            frame("#........"),
+            frame(".#......."),
+            frame("..#......"),
+        );
+        let animation = parse(&source);
+        let lit: Vec<_> = animation
+            .frames
+            .iter()
+            .map(|frame| match &frame.data {
+                FrameData::Bw(frame) => (0..9).position(|x| frame.get(x, 0)),
+                _ => panic!("frames should stay black and white"),
+            })
+            .collect();
+        assert_eq!(lit, [0, 1, 2, 1, 2, 1, 2].map(Some),);
+        assert_eq!(
+            errors(&format!(
+                "\n---\n{}goto nowhere 2 times\n",
+                frame("#........")
+            )),
+            ["unknown label `nowhere`"],
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        fs::write(
+            dir.join("shapes.anim"),
+            format!(
+                "\n---\n{{ name = \"dot\", hidden = true }}\n{}",
+                frame("#........")
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("main.anim"),
+            "[include]\nshapes = \"shapes.anim\"\n---\n@shapes.dot\n\n@shapes\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("self.anim"),
+            "[include]\nme = \"self.anim\"\n---\n",
+        )
+        .unwrap();
+
+        // hidden frames are only used by reference
+        let animation = FileAnimation::load(&dir.join("main.anim")).unwrap();
+        assert_eq!(animation.frames.len(), 1);
+        let looped = FileAnimation::load(&dir.join("self.anim")).err().unwrap();
+        assert!(format!("{looped:#}").contains("includes itself"));
+
+        fs::write(
+            dir.join("broken.anim"),
+            format!("\n---\n{}", frame("#...x....")),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("outer.anim"),
+            "palette = \"01\"\n\n[include]\nbroken = \"broken.anim\"\n---\n",
+        )
+        .unwrap();
+        let err = FileAnimation::load(&dir.join("outer.anim")).err().unwrap();
+        let diagnostic = &err.downcast_ref::<ParseError>().unwrap().diagnostics[0];
+        // points at the key, with the included file's problems attached
+        assert_eq!(
+            (diagnostic.line, diagnostic.column, diagnostic.width),
+            (4, 1, 6),
+        );
+        assert_eq!(diagnostic.message, "failed to include `broken`");
+        assert_eq!(
+            diagnostic.notes,
+            (3..37)
+                .map(|line| format!(
+                    "in `broken.anim`, line {line}, column 5: wrong pixel 'x': should be '.' or '#'"
+                ))
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn includes_need_a_file() {
+        assert_eq!(
+            errors("[include]\na = \"other.anim\"\n---\n"),
+            ["can't include `a`, includes only work in animation files"],
+        );
+    }
+
+    #[test]
+    fn frame_limit() {
+        let message = format!("animation would have more than {MAX_FRAMES} frames");
+        let repeated = format!(
+            "\n---\n{{ repeat = {} }}\n{}",
+            MAX_FRAMES + 1,
+            frame("#........")
+        );
+        assert_eq!(errors(&repeated), [message.as_str()]);
+        let repeated = format!("\n---\n{{ repeat = {} }}\n{}", i64::MAX, frame("#........"));
+        assert_eq!(errors(&repeated), [message.as_str()]);
+
+        // every goto doubles the animation
+        let mut bomb = format!("\n---\nlabel a\n{}", frame("#........"));
+        for _ in 0..20 {
+            bomb.push_str("goto a 1 times\n\n");
         }
+        assert!(errors(&bomb).contains(&message));
     }
 
-    Ok(options.make_gray(frame))
+    #[test]
+    fn sprites() {
+        let source = "\n---\nsprite dot\n##\n\nsprite dim\n80\n\n\
+            place dot at 8 33\nplace dot at -1 0\nplace dot at 32767 -32768\n\n\
+            place dot at 0 0\nplace dim at 0 0 add\n";
+        let animation = parse(source);
+        assert_eq!(animation.frames.len(), 2);
+        let FrameData::Bw(bw) = &animation.frames[0].data else {
+            panic!("black and white sprites should make black and white frames");
+        };
+        let lit: Vec<_> = (0..9)
+            .flat_map(|x| (0..34).map(move |y| (x, y)))
+            .filter(|&(x, y)| bw.get(x, y))
+            .collect();
+        assert_eq!(lit, [(0, 0), (8, 33)]);
+        let FrameData::Gray(gray) = &animation.frames[1].data else {
+            panic!("grayscale sprites should make grayscale frames");
+        };
+        assert_eq!((gray.0[0][0], gray.0[1][0]), (255, 255));
+
+        let errors = errors("\n---\nplace dot at 0 0\nplace dot at 99999 0\n");
+        assert_eq!(errors, ["unknown sprite `dot`", "bad sprite coordinates"]);
+    }
+
+    #[test]
+    fn sprites_fit_the_display() {
+        let source = format!("\n---\nsprite wide\n{}\n", "#".repeat(10));
+        assert_eq!(
+            errors(&source),
+            ["sprite is 10x1, which is bigger than the 9x34 display"],
+        );
+    }
+
+    fn gray(frame: &Frame) -> &GrayFrame {
+        match &frame.data {
+            FrameData::Gray(frame) => frame,
+            _ => panic!("frame should be grayscale"),
+        }
+    }
+
+    #[test]
+    fn compact_rows() {
+        let source = format!(
+            "\n---\n{}{}{}",
+            frame("012345678"),
+            frame("#........"),
+            frame("00 11 22 33 44 55 66 77 ff"),
+        );
+        let animation = parse(&source);
+        assert_eq!(animation.frames.len(), 3);
+        // hex digits spread over 0-255, so each step is 17
+        assert_eq!(gray(&animation.frames[0]).0[2][0], 34);
+        assert_eq!(gray(&animation.frames[0]).0[8][33], 136);
+        assert!(matches!(animation.frames[1].data, FrameData::Bw(_)));
+        assert_eq!(gray(&animation.frames[2]).0[8][0], 255);
+        // writing would turn compact rows into hex ones
+        assert!(animation.has_directives);
+        assert!(!parse(&format!("\n---\n{}", frame("00 00 00 00 00 00 00 00 00"))).has_directives);
+    }
+
+    #[test]
+    fn palettes() {
+        let source = format!(
+            "palette = \".-+#\"\n---\n{}{}",
+            frame("#+-.#+-.#"),
+            frame("00 00 00 00 00 00 00 00 ff"),
+        );
+        let animation = parse(&source);
+        // `.` and `#` are in the palette, so they're not black and white rows
+        let first = gray(&animation.frames[0]);
+        assert_eq!(
+            (0..4).map(|x| first.0[x][0]).collect::<Vec<_>>(),
+            [255, 170, 85, 0],
+        );
+        assert_eq!(gray(&animation.frames[1]).0[8][0], 255);
+        assert!(animation.has_directives);
+    }
+
+    #[test]
+    fn bad_compact_pixels() {
+        assert_eq!(
+            errors(&format!("\n---\n{}", frame("0000x0000"))),
+            vec!["wrong pixel 'x': should be one of \"0123456789abcdef\""; 34],
+        );
+        assert_eq!(
+            errors(&format!("palette = \"ab\"\n---\n{}", frame("abababa0b"))),
+            vec!["wrong pixel '0': should be one of \"ab\""; 34],
+        );
+        assert_eq!(
+            errors("palette = \"a\"\n---\n"),
+            ["palette needs at least two characters"],
+        );
+        assert_eq!(
+            errors("palette = \"aba\"\n---\n"),
+            ["palette contains 'a' twice"],
+        );
+        assert_eq!(
+            errors("palette = \"a b\"\n---\n"),
+            ["palette can't contain ' '"],
+        );
+    }
+
+    #[test]
+    fn ambiguous_rows() {
+        let ambiguous = "ambiguous row: write hex pixels with two digits, like `0f`, \
+                         or compact pixels without spaces";
+        assert_eq!(
+            errors(&format!("\n---\n{}", frame("0 1 2 3 4 5 6 7 8"))),
+            vec![ambiguous; 34],
+        );
+        assert_eq!(
+            errors(&format!(
+                "palette = \".-+#\"\n---\n{}",
+                frame("# + - . # + - . #")
+            )),
+            vec![ambiguous; 34],
+        );
+        // both readings agree, and two-digit pixels can't be compact
+        let animation = parse(&format!(
+            "\n---\n{}{}",
+            frame("0 0 0 0 0 0 0 0 0"),
+            frame("0 1 2 3 4 5 6 7 08"),
+        ));
+        assert_eq!(gray(&animation.frames[1]).0[8][0], 8);
+        assert_eq!(
+            errors(&format!(
+                "\n---\n000000000\n0000 0000\n{}",
+                "000000000\n".repeat(32),
+            )),
+            ["compact rows can't contain whitespace"],
+        );
+    }
+
+    #[test]
+    fn one_column_sprites() {
+        // without whitespace, rows are compact even if they look like a hex number
+        let animation = parse("\n---\nsprite thin\nf\n0\n\nsprite pair\nff\n");
+        assert_eq!(animation.sprites["thin"].rows, [vec![255], vec![0]]);
+        assert_eq!(animation.sprites["pair"].rows, [vec![255, 255]]);
+        let animation = parse("\n---\nsprite pair\nff 80\n");
+        assert_eq!(animation.sprites["pair"].rows, [vec![255, 128]]);
+    }
+
+    #[test]
+    fn gray_tweens() {
+        let source = format!(
+            "\n---\n{{ tween = {{ frames = 3 }} }}\n{}{}",
+            frame("00 00 00 00 00 00 00 00 00"),
+            frame("80 80 80 80 80 80 80 80 80"),
+        );
+        let animation = parse(&source);
+        assert!(animation.has_directives);
+        let values: Vec<_> = animation
+            .frames
+            .iter()
+            .map(|frame| match &frame.data {
+                FrameData::Gray(frame) => frame.0[4][17],
+                _ => panic!("grayscale keyframes should tween to grayscale frames"),
+            })
+            .collect();
+        assert_eq!(values, [0, 32, 64, 96, 128]);
+
+        let eased = Tween {
+            frames: 1,
+            easing: Easing::EaseIn,
+            positions: false,
+        };
+        let between = eased.between(&animation.frames[0], &animation.frames[4]);
+        assert!(matches!(&between[0].data, FrameData::Gray(frame) if frame.0[0][0] == 32));
+    }
+
+    #[test]
+    fn position_tweens() {
+        let source = "\n---\nsprite dot\n#\n\n\
+            { tween = { frames = 3, positions = true } }\nplace dot at 0 0\n\n\
+            place dot at 4 8\n";
+        let animation = parse(source);
+        let lit: Vec<_> = animation
+            .frames
+            .iter()
+            .map(|frame| {
+                let FrameData::Bw(bw) = &frame.data else {
+                    panic!("black and white sprites should move in black and white frames");
+                };
+                (0..9)
+                    .flat_map(|x| (0..34).map(move |y| (x, y)))
+                    .filter(|&(x, y)| bw.get(x, y))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(lit, [[(0, 0)], [(1, 2)], [(2, 4)], [(3, 6)], [(4, 8)]]);
+
+        let source = format!(
+            "\n---\nsprite dot\n#\n\n\
+             {{ tween = {{ frames = 3, positions = true }} }}\nplace dot at 0 0\n\n{}",
+            frame("#........"),
+        );
+        assert_eq!(
+            errors(&source),
+            ["tweening positions needs both keyframes to place the same sprites"],
+        );
+    }
+
+    #[test]
+    fn bw_tweens_follow_brightness() {
+        let source = format!(
+            "\n---\n{{ tween = {{ frames = 1 }} }}\n{}{}",
+            frame("#########"),
+            frame("........."),
+        );
+        let animation = parse(&source);
+        assert_eq!(animation.frames.len(), 3);
+        let FrameData::Blend(blend) = &animation.frames[1].data else {
+            panic!("black and white keyframes should tween to blends");
+        };
+        // halfway between lit and off, at whatever lit means on the display
+        assert_eq!(blend.to_gray(255).0[0][0], 128);
+        assert_eq!(blend.to_gray(40).0[0][0], 20);
+        let merged = animation.frames[0]
+            .clone()
+            .merge(animation.frames[1].clone(), 40);
+        assert_eq!(merged.data.into_gray(40).0[0][0], 40);
+    }
 }
@@ -0,0 +1,127 @@
+use std::{fmt, ops::Range};
+
+/// A single problem in an animation file.
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, in bytes.
+    pub column: usize,
+    /// How many bytes to underline; at least one caret is always drawn.
+    pub width: usize,
+    pub message: String,
+    /// Related problems elsewhere, like in an included file.
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Points at `part` inside of `raw_line`; `part` must be a subslice of `raw_line`.
+    pub fn at(line: usize, raw_line: &str, part: &str, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column: column_of(raw_line, part),
+            width: part.len(),
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Points at a byte range of a multi-line `text` which starts at `first_line`.
+    pub fn in_text(
+        text: &str,
+        first_line: usize,
+        span: Option<Range<usize>>,
+        message: impl Into<String>,
+    ) -> Self {
+        let span = span.unwrap_or(0..0);
+        let start = span.start.min(text.len());
+        let before = &text[..start];
+        let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+        Self {
+            line: first_line + before.matches('\n').count(),
+            column: start - line_start + 1,
+            width: span.len(),
+            // TOML errors span several lines sometimes
+            message: message.into().trim().replace('\n', ", "),
+            notes: Vec::new(),
+        }
+    }
+}
+
+/// 1-based column of `part` inside of `raw_line`.
+pub fn column_of(raw_line: &str, part: &str) -> usize {
+    (part.as_ptr() as usize).saturating_sub(raw_line.as_ptr() as usize) + 1
+}
+
+/// Span of the key an "unknown field" error is about, as `toml` has none for inline tables.
+pub fn unknown_key_span(line: &str, message: &str) -> Option<Range<usize>> {
+    let key = message.strip_prefix("unknown field `")?.split('`').next()?;
+    key_span(line, key)
+}
+
+/// Span of the first `key = …` assignment in TOML `text`, quoted or not.
+pub fn key_span(text: &str, key: &str) -> Option<Range<usize>> {
+    let (start, _) = text.match_indices(key).find(|&(idx, _)| {
+        let before = text[..idx].trim_end_matches(['"', '\'']);
+        let after = text[idx + key.len()..].trim_start_matches(['"', '\'']);
+        !before.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '-')
+            && after.trim_start().starts_with('=')
+    })?;
+    Some(start..start + key.len())
+}
+
+/// Every problem found in an animation file, rendered with source snippets.
+#[derive(Debug)]
+pub struct ParseError {
+    pub diagnostics: Vec<Diagnostic>,
+    source: String,
+}
+
+impl ParseError {
+    pub fn new(mut diagnostics: Vec<Diagnostic>, source: &str) -> Self {
+        diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+        Self {
+            diagnostics,
+            source: source.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.diagnostics.len();
+        write!(
+            f,
+            "{count} error{} in animation file",
+            if count == 1 { "" } else { "s" }
+        )?;
+
+        for diagnostic in &self.diagnostics {
+            let Diagnostic {
+                line,
+                column,
+                width,
+                message,
+                notes,
+            } = diagnostic;
+            let source_line = self.source.lines().nth(line - 1).unwrap_or_default();
+            let gutter = " ".repeat(line.to_string().len());
+            let underline = "^".repeat((*width).max(1));
+            write!(
+                f,
+                "\n\nline {line}, column {column}: {message}\n\
+                 {gutter} |\n\
+                 {line} | {source_line}\n\
+                 {gutter} | {:pad$}{underline}",
+                "",
+                pad = column - 1,
+            )?;
+            for note in notes {
+                write!(f, "\n{gutter} = note: {note}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
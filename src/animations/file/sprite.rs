@@ -0,0 +1,76 @@
+use std::str::FromStr;
+
+use eyre::bail;
+
+use crate::animations::GrayFrame;
+
+/// Image smaller than the display, placed onto frames with `place`.
+#[derive(Clone)]
+pub struct Sprite {
+    /// Rows of pixels, all of the same width.
+    pub rows: Vec<Vec<u8>>,
+    /// Whether the sprite was written with `.` and `#`.
+    pub bw: bool,
+}
+
+/// A sprite drawn onto a frame by `place`.
+#[derive(Clone)]
+pub struct Placement {
+    pub name: String,
+    pub sprite: Sprite,
+    /// Position of the top-left corner.
+    pub x: i16,
+    pub y: i16,
+    pub blend: Blend,
+}
+
+/// How sprite pixels are combined with what's already in the frame.
+#[derive(Clone, Copy, Default)]
+pub enum Blend {
+    /// Lit sprite pixels replace frame pixels, dark ones are transparent.
+    #[default]
+    Over,
+    /// Every sprite pixel replaces the frame pixel.
+    Replace,
+    Max,
+    /// Saturating sum.
+    Add,
+}
+
+impl FromStr for Blend {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        Ok(match s {
+            "over" => Self::Over,
+            "replace" => Self::Replace,
+            "max" => Self::Max,
+            "add" => Self::Add,
+            _ => {
+                bail!("unknown blend mode `{s}`: should be one of `over`, `replace`, `max`, `add`")
+            }
+        })
+    }
+}
+
+impl Sprite {
+    /// Draws the sprite with its top-left corner at `(x, y)`, clipping whatever doesn't fit.
+    pub fn draw(&self, frame: &mut GrayFrame, x: i16, y: i16, blend: Blend) {
+        for (dy, row) in self.rows.iter().enumerate() {
+            for (dx, &value) in row.iter().enumerate() {
+                // sprites are at most 9x34 and positions are `i16`, so this can't overflow
+                let (fx, fy) = (i32::from(x) + dx as i32, i32::from(y) + dy as i32);
+                if !(0..9).contains(&fx) || !(0..34).contains(&fy) {
+                    continue;
+                }
+                let pixel = &mut frame.0[fx as usize][fy as usize];
+                *pixel = match blend {
+                    Blend::Over if value == 0 => *pixel,
+                    Blend::Over | Blend::Replace => value,
+                    Blend::Max => (*pixel).max(value),
+                    Blend::Add => pixel.saturating_add(value),
+                };
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+use super::sprite::Placement;
+use crate::animations::{BlendFrame, Frame, FrameData, GrayFrame};
+
+/// Generated frames between a keyframe and the one after it.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tween {
+    /// How many frames to insert.
+    pub frames: usize,
+    #[serde(default)]
+    pub easing: Easing,
+    /// Move sprites from where one keyframe places them to where the next one does,
+    /// instead of fading between their pixels.
+    #[serde(default)]
+    pub positions: bool,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps linear progress from 0 to 1 onto eased progress.
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t,
+            Self::EaseOut => 1. - (1. - t) * (1. - t),
+            Self::EaseInOut if t < 0.5 => 2. * t * t,
+            Self::EaseInOut => 1. - (2. - 2. * t).powi(2) / 2.,
+        }
+    }
+}
+
+impl Tween {
+    /// Intermediate frames from `from` to `to`, excluding both of them.
+    /// They take their duration and fullscreen from `from`; black and white
+    /// keyframes fade at whatever brightness they're drawn at.
+    pub fn between(&self, from: &Frame, to: &Frame) -> Vec<Frame> {
+        let (start, end) = (to_blend(from), to_blend(to));
+        (1..=self.frames)
+            .map(|step| {
+                let t = self.easing.apply(step as f32 / (self.frames + 1) as f32);
+                let blend = BlendFrame {
+                    gray: interpolate(&start.gray, &end.gray, t),
+                    bw: interpolate(&start.bw, &end.bw, t),
+                };
+                let data = if blend.bw == GrayFrame::default() {
+                    FrameData::Gray(blend.gray)
+                } else {
+                    FrameData::Blend(Box::new(blend))
+                };
+                Frame {
+                    data,
+                    min_duration: from.min_duration,
+                    fullscreen: from.fullscreen,
+                }
+            })
+            .collect()
+    }
+
+    /// Placements of intermediate frames moving sprites from `from` to `to`, excluding both;
+    /// `None` unless both place the same sprites in the same order.
+    pub fn moving(&self, from: &[Placement], to: &[Placement]) -> Option<Vec<Vec<Placement>>> {
+        let same_sprites = from.len() == to.len()
+            && from
+                .iter()
+                .zip(to)
+                .all(|(start, end)| start.name == end.name);
+        if !same_sprites {
+            return None;
+        }
+        let steps = (1..=self.frames).map(|step| {
+            let t = self.easing.apply(step as f32 / (self.frames + 1) as f32);
+            from.iter()
+                .zip(to)
+                .map(|(start, end)| Placement {
+                    x: lerp(start.x, end.x, t),
+                    y: lerp(start.y, end.y, t),
+                    ..start.clone()
+                })
+                .collect()
+        });
+        Some(steps.collect())
+    }
+}
+
+fn lerp(start: i16, end: i16, t: f32) -> i16 {
+    let (a, b) = (f32::from(start), f32::from(end));
+    // float to int casts saturate, and the result is between `start` and `end` anyway
+    (a + (b - a) * t).round() as i16
+}
+
+fn interpolate(start: &GrayFrame, end: &GrayFrame, t: f32) -> GrayFrame {
+    let mut frame = GrayFrame::default();
+    for x in 0..9 {
+        for y in 0..34 {
+            let (a, b) = (f32::from(start.0[x][y]), f32::from(end.0[x][y]));
+            frame.0[x][y] = (a + (b - a) * t).round() as u8;
+        }
+    }
+    frame
+}
+
+fn to_blend(frame: &Frame) -> BlendFrame {
+    match &frame.data {
+        FrameData::Gray(frame) => BlendFrame {
+            gray: frame.clone(),
+            bw: GrayFrame::default(),
+        },
+        FrameData::Bw(frame) => BlendFrame {
+            gray: GrayFrame::default(),
+            bw: GrayFrame::from_bw(frame.clone(), 255),
+        },
+        FrameData::Blend(frame) => (**frame).clone(),
+    }
+}
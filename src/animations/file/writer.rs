@@ -0,0 +1,99 @@
+use std::fmt::Write as _;
+
+use crate::animations::{
+    Frame, FrameData, GrayFrame, IsFrame as _,
+    file::{FileOptions, FrameOptions},
+};
+
+/// Serializes frames into the canonical animation file format.
+///
+/// Identical consecutive frames are collapsed with `repeat`, and the most common
+/// frame settings are moved into the header.
+pub fn write_frames<'a>(frames: impl IntoIterator<Item = &'a Frame>, default_offset: i8) -> String {
+    let mut runs: Vec<(&Frame, usize)> = Vec::new();
+    for frame in frames {
+        match runs.last_mut() {
+            Some((last, count)) if *last == frame => *count += 1,
+            _ => runs.push((frame, 1)),
+        }
+    }
+
+    let header = FileOptions {
+        default_offset,
+        min_duration: most_common(runs.iter().map(|(frame, _)| frame.min_duration))
+            .unwrap_or_default(),
+        fullscreen: most_common(runs.iter().map(|(frame, _)| frame.fullscreen)).unwrap_or(false),
+        ..FileOptions::default()
+    };
+
+    let mut result = toml::to_string(&header).expect("header is always serializable");
+    result.push_str("---\n");
+    for (frame, count) in runs {
+        result.push('\n');
+
+        let options = FrameOptions {
+            repeat: (count > 1).then_some(count),
+            fullscreen: (frame.fullscreen != header.fullscreen).then_some(frame.fullscreen),
+            min_duration: (frame.min_duration != header.min_duration).then_some(frame.min_duration),
+            ..FrameOptions::default()
+        };
+        if options.repeat.is_some()
+            || options.fullscreen.is_some()
+            || options.min_duration.is_some()
+        {
+            let options =
+                toml::Value::try_from(&options).expect("frame options are always serializable");
+            writeln!(result, "{options}").unwrap();
+        }
+
+        write_grid(&mut result, frame);
+    }
+    result
+}
+
+fn write_grid(into: &mut String, frame: &Frame) {
+    for y in 0..34 {
+        match &frame.data {
+            FrameData::Bw(frame) => {
+                for x in 0..9 {
+                    into.push(if frame.get(x, y) { '#' } else { '.' });
+                }
+            }
+            FrameData::Gray(frame) => write_gray_row(into, frame, y),
+            // only tweens make these, so there are no keyframes to keep them as
+            FrameData::Blend(frame) => write_gray_row(into, &frame.to_gray(255), y),
+        }
+        into.push('\n');
+    }
+}
+
+fn write_gray_row(into: &mut String, frame: &GrayFrame, y: u8) {
+    for x in 0..9 {
+        if x != 0 {
+            into.push(' ');
+        }
+        write!(into, "{:02x}", frame.get(x, y)).unwrap();
+    }
+}
+
+/// Most frequent value, preferring the one that was seen first on ties.
+fn most_common<T: PartialEq>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(other, _)| *other == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+
+    let mut best: Option<(T, usize)> = None;
+    for (value, count) in counts {
+        if best
+            .as_ref()
+            .is_none_or(|(_, best_count)| count > *best_count)
+        {
+            best = Some((value, count));
+        }
+    }
+    best.map(|(value, _)| value)
+}
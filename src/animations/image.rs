@@ -0,0 +1,189 @@
+use std::{fs::File, io::BufReader, path::Path, time::Duration};
+
+use ::image::{AnimationDecoder as _, GrayImage, RgbaImage, codecs::gif::GifDecoder};
+use eyre::{WrapErr as _, ensure};
+
+use crate::{
+    animations::{Frame, FrameData, GrayFrame, IsFrame as _, file::FileAnimation},
+    config,
+    proto::BwFrame,
+};
+
+/// Loads a list of PNG and GIF files as a sequence of frames.
+pub fn load(config: &config::ImageAnimation) -> eyre::Result<FileAnimation> {
+    ensure!(!config.paths.is_empty(), "image animation has no `paths`");
+
+    let mut frames = Vec::new();
+    for path in &config.paths {
+        let is_gif = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gif"));
+        let images = if is_gif {
+            load_gif(path)?
+        } else {
+            let image = ::image::open(path)
+                .wrap_err_with(|| format!("failed to read image `{}`", path.display()))?;
+            vec![(image.to_rgba8(), None)]
+        };
+
+        for (image, delay) in images {
+            let image = luminance(&image);
+            let (width, height) = image.dimensions();
+            ensure!(
+                config.crop
+                    || (width + u32::from(config.x) <= 9 && height + u32::from(config.y) <= 34),
+                "image `{}` is {width}x{height} at ({}, {}), which doesn't fit 9x34 display; \
+                 set `crop = true` to cut it",
+                path.display(),
+                config.x,
+                config.y,
+            );
+            frames.push(to_frame(
+                &image,
+                config,
+                delay.unwrap_or(config.frame_duration),
+            ));
+        }
+    }
+
+    Ok(FileAnimation::from_frames(frames))
+}
+
+fn load_gif(path: &Path) -> eyre::Result<Vec<(RgbaImage, Option<Duration>)>> {
+    let file =
+        File::open(path).wrap_err_with(|| format!("failed to open image `{}`", path.display()))?;
+    let decoder = GifDecoder::new(BufReader::new(file))
+        .wrap_err_with(|| format!("failed to decode GIF `{}`", path.display()))?;
+    decoder
+        .into_frames()
+        .map(|frame| {
+            let frame =
+                frame.wrap_err_with(|| format!("failed to decode GIF `{}`", path.display()))?;
+            let delay = Duration::from(frame.delay());
+            // zero delay is usually meant as "as fast as possible", let the config decide
+            let delay = (!delay.is_zero()).then_some(delay);
+            Ok((frame.into_buffer(), delay))
+        })
+        .collect()
+}
+
+/// Converts to grayscale, treating transparent pixels as dark.
+fn luminance(image: &RgbaImage) -> GrayImage {
+    let luma = ::image::DynamicImage::ImageRgba8(image.clone()).to_luma_alpha8();
+    GrayImage::from_fn(luma.width(), luma.height(), |x, y| {
+        let [value, alpha] = luma.get_pixel(x, y).0;
+        // cast is safe, as the product is at most 255 * 255
+        ::image::Luma([(u16::from(value) * u16::from(alpha) / 255) as u8])
+    })
+}
+
+fn to_frame(image: &GrayImage, config: &config::ImageAnimation, min_duration: Duration) -> Frame {
+    let mut gray = GrayFrame::default();
+    let mut bw = BwFrame::default();
+    for (ix, iy, pixel) in image.enumerate_pixels() {
+        let (x, y) = (ix + u32::from(config.x), iy + u32::from(config.y));
+        if x > 8 || y > 33 {
+            // cropped
+            continue;
+        }
+        // casts are safe because of the check above
+        let (x, y) = (x as u8, y as u8);
+        let value = pixel.0[0];
+        match config.threshold {
+            Some(threshold) => bw.set(x, y, value >= threshold),
+            None => gray.set(x, y, value),
+        }
+    }
+
+    let data = match config.threshold {
+        Some(_) => FrameData::Bw(bw),
+        None => FrameData::Gray(gray),
+    };
+    Frame {
+        data,
+        min_duration,
+        fullscreen: config.fullscreen,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use ::image::{
+        Delay, Rgba,
+        codecs::gif::{GifEncoder, Repeat},
+    };
+
+    use super::*;
+
+    fn config(paths: Vec<PathBuf>) -> config::ImageAnimation {
+        let config: config::ImageAnimation = toml::from_str("paths = []").unwrap();
+        config::ImageAnimation { paths, ..config }
+    }
+
+    fn gray(frame: &Frame) -> &GrayFrame {
+        match &frame.data {
+            FrameData::Gray(frame) => frame,
+            _ => panic!("images without threshold should make grayscale frames"),
+        }
+    }
+
+    #[test]
+    fn png() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dots.png");
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        // transparent pixels are dark
+        image.put_pixel(1, 0, Rgba([255, 255, 255, 0]));
+        image.save(&path).unwrap();
+
+        let mut placed = config(vec![path]);
+        (placed.x, placed.y) = (3, 5);
+        let gray_frames = load(&placed).unwrap().frames;
+        assert_eq!(gray_frames.len(), 1);
+        let frame = gray(&gray_frames[0]);
+        assert_eq!((frame.0[3][5], frame.0[4][5], frame.0[0][0]), (255, 0, 0));
+        assert_eq!(gray_frames[0].min_duration, Duration::from_millis(100));
+
+        placed.threshold = Some(128);
+        let FrameData::Bw(frame) = &load(&placed).unwrap().frames[0].data else {
+            panic!("images with threshold should make black and white frames");
+        };
+        assert!(frame.get(3, 5) && !frame.get(4, 5));
+
+        (placed.x, placed.crop) = (8, false);
+        let too_wide = load(&placed).map(drop).unwrap_err();
+        assert!(format!("{too_wide:#}").contains("set `crop = true`"));
+        placed.crop = true;
+        assert!(load(&placed).is_ok());
+    }
+
+    #[test]
+    fn gif_delays() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blink.gif");
+        {
+            let mut encoder = GifEncoder::new(File::create(&path).unwrap());
+            encoder.set_repeat(Repeat::Infinite).unwrap();
+            for (value, delay) in [(255, 30), (0, 0)] {
+                let image = RgbaImage::from_pixel(9, 34, Rgba([value, value, value, 255]));
+                encoder
+                    .encode_frame(::image::Frame::from_parts(
+                        image,
+                        0,
+                        0,
+                        Delay::from_saturating_duration(Duration::from_millis(delay)),
+                    ))
+                    .unwrap();
+            }
+        }
+        let frames = load(&config(vec![path])).unwrap().frames;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(gray(&frames[0]).0[8][33], 255);
+        assert_eq!(frames[0].min_duration, Duration::from_millis(30));
+        // no delay falls back to the configured frame duration
+        assert_eq!(frames[1].min_duration, Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    f32::consts::TAU,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    animations::{Animation, Frame, FrameData, GrayFrame, IsFrame as _},
+    config::{FillDirection, ProgressStyle},
+};
+
+struct State {
+    value: u8,
+    done: bool,
+    last_update: Instant,
+    timeout: Duration,
+}
+
+impl State {
+    fn is_expired(&self) -> bool {
+        self.last_update.elapsed() > self.timeout
+    }
+}
+
+/// Controls a running progress bar from outside of the display thread.
+#[derive(Clone)]
+pub struct ProgressHandle(Arc<Mutex<State>>);
+
+impl ProgressHandle {
+    /// Sets progress value in percents, clamping it to 100.
+    pub fn set(&self, value: u8) {
+        let mut state = self.0.lock().unwrap();
+        state.value = value.min(100);
+        state.last_update = Instant::now();
+    }
+
+    pub fn finish(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.value = 100;
+        state.done = true;
+    }
+
+    /// Whether this bar was finished or didn't get any updates for too long.
+    pub fn is_finished(&self) -> bool {
+        let state = self.0.lock().unwrap();
+        state.done || state.is_expired()
+    }
+}
+
+pub struct ProgressBar {
+    state: Arc<Mutex<State>>,
+    style: ProgressStyle,
+    started: Instant,
+    finished: bool,
+}
+
+pub fn start(style: ProgressStyle) -> (ProgressHandle, Animation) {
+    let state = Arc::new(Mutex::new(State {
+        value: 0,
+        done: false,
+        last_update: Instant::now(),
+        timeout: style.timeout,
+    }));
+    let bar = ProgressBar {
+        state: Arc::clone(&state),
+        style,
+        started: Instant::now(),
+        finished: false,
+    };
+    (ProgressHandle(state), Box::new(bar))
+}
+
+impl ProgressBar {
+    fn render(&self, value: u8) -> GrayFrame {
+        let (length, vertical) = match self.style.direction {
+            FillDirection::Up | FillDirection::Down => (34u8, true),
+            FillDirection::Left | FillDirection::Right => (9u8, false),
+        };
+        let filled = f32::from(value) / 100.0 * f32::from(length);
+        let pulse = match self.style.pulse_period {
+            Some(period) if !period.is_zero() => {
+                let phase = self.started.elapsed().as_secs_f32() / period.as_secs_f32();
+                // oscillate between 30% and 100% of brightness
+                0.65 + 0.35 * (phase * TAU).cos()
+            }
+            _ => 1.0,
+        };
+
+        let mut frame = GrayFrame::default();
+        for idx in 0..length {
+            let coverage = (filled - f32::from(idx)).clamp(0.0, 1.0);
+            if coverage == 0.0 {
+                break;
+            }
+            let t = f32::from(idx) / f32::from(length - 1);
+            let base = f32::from(self.style.start_brightness) * (1.0 - t)
+                + f32::from(self.style.end_brightness) * t;
+            // cast is safe, as the result is always in 0..=255
+            let brightness = (base * coverage * pulse).round() as u8;
+
+            if vertical {
+                let y = match self.style.direction {
+                    FillDirection::Up => 33 - idx,
+                    _ => idx,
+                };
+                for x in 0..9 {
+                    frame.set(x, y, brightness);
+                }
+            } else {
+                let x = match self.style.direction {
+                    FillDirection::Left => 8 - idx,
+                    _ => idx,
+                };
+                for y in 0..34 {
+                    frame.set(x, y, brightness);
+                }
+            }
+        }
+        frame
+    }
+}
+
+impl Iterator for ProgressBar {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let (value, done) = {
+            let state = self.state.lock().unwrap();
+            if state.is_expired() && !state.done {
+                return None;
+            }
+            (state.value, state.done)
+        };
+
+        let min_duration = if done {
+            // show the full bar for a bit before going away
+            self.finished = true;
+            self.style.done_duration
+        } else {
+            self.style.frame_duration
+        };
+
+        Some(Frame {
+            data: FrameData::Gray(self.render(value)),
+            min_duration,
+            fullscreen: false,
+        })
+    }
+}
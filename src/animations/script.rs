@@ -0,0 +1,318 @@
+//! Animations written as Rhai scripts.
+//!
+//! A script must define `fn frame(t, n)`, which is called for every frame with time
+//! since start in seconds and the frame number. It should return a canvas (created
+//! with `canvas()`) to draw, or `()` to end the animation. An optional `fn init()`
+//! may return the initial state, which is then available as `this` in `frame`.
+//! Top-level statements are evaluated once, before `init`.
+
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use eyre::{WrapErr as _, eyre};
+use rhai::{
+    AST, CallFnOptions, Dynamic, Engine, INT, Scope, module_resolvers::DummyModuleResolver,
+};
+use tracing::error;
+
+use crate::{
+    animations::{Frame, FrameData, GrayFrame},
+    config,
+};
+
+#[derive(Clone)]
+pub struct ScriptBuilder {
+    ast: Arc<AST>,
+    config: config::ScriptAnimation,
+}
+
+impl ScriptBuilder {
+    pub fn new(config: config::ScriptAnimation) -> eyre::Result<Self> {
+        let path = &config.path;
+        let raw = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read script `{}`", path.display()))?;
+        let ast = sandboxed_engine(config.max_operations)
+            .compile(&raw)
+            .map_err(|err| eyre!("failed to compile script `{}`: {err}", path.display()))?;
+        eyre::ensure!(
+            ast.iter_functions()
+                .any(|f| f.name == "frame" && f.params.len() == 2),
+            "script `{}` doesn't define `fn frame(t, n)`",
+            path.display(),
+        );
+
+        Ok(Self {
+            ast: Arc::new(ast),
+            config,
+        })
+    }
+
+    pub fn build(&self) -> Script {
+        let deadline = Arc::new(Mutex::new(Instant::now()));
+        Script {
+            engine: make_engine(&self.config, Arc::clone(&deadline)),
+            ast: Arc::clone(&self.ast),
+            scope: Scope::new(),
+            state: None,
+            deadline,
+            config: self.config.clone(),
+            frame: 0,
+        }
+    }
+}
+
+/// Engine with limits and without access to the outside world; `Engine::new()` alone
+/// would let `import` run any script file on disk.
+fn sandboxed_engine(max_operations: u64) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(max_operations)
+        .set_max_call_levels(64)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(64 * 1024)
+        .set_max_array_size(64 * 1024)
+        .set_max_map_size(64 * 1024);
+    engine
+}
+
+fn make_engine(config: &config::ScriptAnimation, deadline: Arc<Mutex<Instant>>) -> Engine {
+    let mut engine = sandboxed_engine(config.max_operations);
+    engine
+        .on_progress(move |_ops| {
+            if Instant::now() > *deadline.lock().unwrap() {
+                Some("frame time budget exceeded".into())
+            } else {
+                None
+            }
+        })
+        .on_print(|text| tracing::info!(target: "fw_lights::script", "{text}"))
+        .on_debug(|text, _, pos| tracing::debug!(target: "fw_lights::script", %pos, "{text}"));
+
+    engine
+        .register_type_with_name::<GrayFrame>("Canvas")
+        .register_fn("canvas", GrayFrame::default)
+        .register_fn("width", || 9 as INT)
+        .register_fn("height", || 34 as INT)
+        .register_fn(
+            "set",
+            |frame: &mut GrayFrame, x: INT, y: INT, value: INT| {
+                if let Some(pixel) = pixel_mut(frame, x, y) {
+                    // cast is safe because of the clamp
+                    *pixel = value.clamp(0, 255) as u8;
+                }
+            },
+        )
+        .register_fn("get", |frame: &mut GrayFrame, x: INT, y: INT| {
+            pixel_mut(frame, x, y).map_or(0, |pixel| *pixel as INT)
+        })
+        .register_fn("fill", |frame: &mut GrayFrame, value: INT| {
+            let value = value.clamp(0, 255) as u8;
+            frame.0 = [[value; 34]; 9];
+        })
+        .register_fn("clear", |frame: &mut GrayFrame| {
+            *frame = GrayFrame::default();
+        });
+
+    engine
+}
+
+fn pixel_mut(frame: &mut GrayFrame, x: INT, y: INT) -> Option<&mut u8> {
+    let x = usize::try_from(x).ok()?;
+    let y = usize::try_from(y).ok()?;
+    frame.0.get_mut(x)?.get_mut(y)
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: Arc<AST>,
+    scope: Scope<'static>,
+    // `None` until `init` is called
+    state: Option<Dynamic>,
+    deadline: Arc<Mutex<Instant>>,
+    config: config::ScriptAnimation,
+    frame: u32,
+}
+
+impl Script {
+    fn reset_deadline(&self) {
+        *self.deadline.lock().unwrap() = Instant::now() + self.config.frame_budget;
+    }
+
+    fn step(&mut self) -> eyre::Result<Option<GrayFrame>> {
+        let path = &self.config.path;
+        if self.state.is_none() {
+            self.reset_deadline();
+            let has_init = self
+                .ast
+                .iter_functions()
+                .any(|f| f.name == "init" && f.params.is_empty());
+            self.engine
+                .run_ast_with_scope(&mut self.scope, &self.ast)
+                .map_err(|err| eyre!("script `{}` failed: {err}", path.display()))?;
+            let state = if has_init {
+                let options = CallFnOptions::new().eval_ast(false);
+                self.engine
+                    .call_fn_with_options::<Dynamic>(
+                        options,
+                        &mut self.scope,
+                        &self.ast,
+                        "init",
+                        (),
+                    )
+                    .map_err(|err| eyre!("`init` in script `{}` failed: {err}", path.display()))?
+            } else {
+                Dynamic::UNIT
+            };
+            self.state = Some(state);
+        }
+
+        self.reset_deadline();
+        let t = (self.config.frame_duration * self.frame).as_secs_f64();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(self.state.as_mut().unwrap());
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                "frame",
+                (t, self.frame as INT),
+            )
+            .map_err(|err| eyre!("`frame` in script `{}` failed: {err}", path.display()))?;
+        self.frame += 1;
+
+        if result.is_unit() {
+            return Ok(None);
+        }
+        result.try_cast::<GrayFrame>().map(Some).ok_or_else(|| {
+            eyre!(
+                "`frame` in script `{}` returned something other than a canvas",
+                path.display()
+            )
+        })
+    }
+}
+
+impl Iterator for Script {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.step() {
+            Ok(frame) => Some(Frame {
+                data: FrameData::Gray(frame?),
+                min_duration: self.config.frame_duration,
+                fullscreen: false,
+            }),
+            Err(err) => {
+                error!(%err, "stopping script animation");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use super::*;
+
+    fn write(dir: &Path, name: &str, source: &str) -> PathBuf {
+        let path = dir.join(format!("{name}.rhai"));
+        fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn script(source: &str, max_operations: u64, frame_budget: Duration) -> Script {
+        let dir = tempfile::tempdir().unwrap();
+        ScriptBuilder::new(config::ScriptAnimation {
+            path: write(dir.path(), "script", source),
+            frame_duration: Duration::from_millis(100),
+            max_operations,
+            frame_budget,
+        })
+        .unwrap()
+        .build()
+    }
+
+    fn gray(frame: Frame) -> GrayFrame {
+        match frame.data {
+            FrameData::Gray(frame) => frame,
+            _ => panic!("scripts should make grayscale frames"),
+        }
+    }
+
+    #[test]
+    fn drawing() {
+        let source = r#"
+            fn init() { #{ level: 10 } }
+            fn frame(t, n) {
+                if n == 2 { return; }
+                this.level += 10;
+                let c = canvas();
+                c.fill(this.level);
+                c.set(1, 2, 300);
+                c.set(-1, 0, 5);
+                c.set(width() - 1, height() - 1, c.get(1, 2) / 2);
+                c.set(0, 0, c.get(9, 0));
+                c
+            }
+        "#;
+        let frames: Vec<_> = script(source, 1_000_000, Duration::from_secs(10))
+            .map(gray)
+            .collect();
+        assert_eq!(frames.len(), 2);
+        let mut expected = GrayFrame([[20; 34]; 9]);
+        expected.0[1][2] = 255;
+        expected.0[8][33] = 127;
+        expected.0[0][0] = 0;
+        assert!(frames[0] == expected);
+        assert_eq!(frames[1].0[4][4], 30);
+    }
+
+    #[test]
+    fn runaway_scripts_are_stopped() {
+        let source = "fn frame(t, n) { loop {} }";
+        let started = Instant::now();
+        // the wall-clock budget stops it even with practically unlimited operations
+        let mut by_time = script(source, u64::MAX, Duration::from_millis(10));
+        assert!(by_time.next().is_none());
+        let mut by_operations = script(source, 1000, Duration::from_secs(60));
+        assert!(by_operations.next().is_none());
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn imports_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let module = write(dir.path(), "module", "fn level() { 255 }");
+        let source = format!(
+            "import {:?} as m;\nfn frame(t, n) {{ let c = canvas(); c.fill(m::level()); c }}",
+            module.display().to_string()
+        );
+        let mut script = script(&source, 1_000_000, Duration::from_secs(10));
+        assert!(script.next().is_none());
+    }
+
+    #[test]
+    fn frame_is_required() {
+        let dir = tempfile::tempdir().unwrap();
+        let builder = ScriptBuilder::new(config::ScriptAnimation {
+            path: write(dir.path(), "no-frame", "fn draw(t, n) { canvas() }"),
+            frame_duration: Duration::from_millis(100),
+            max_operations: 1000,
+            frame_budget: Duration::from_millis(10),
+        });
+        let err = builder.err().unwrap().to_string();
+        assert!(err.ends_with("doesn't define `fn frame(t, n)`"), "{err}");
+    }
+}
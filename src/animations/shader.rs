@@ -0,0 +1,540 @@
+use std::{f32::consts::PI, str::FromStr, time::Duration};
+
+use eyre::{bail, ensure, eyre};
+use serde::Deserialize;
+
+use crate::{
+    animations::{Frame, FrameData, GrayFrame},
+    config,
+};
+
+/// Per-pixel arithmetic expression.
+///
+/// Evaluates to brightness in `0.0..=1.0`; anything outside is clamped.
+/// Available variables are `x`, `y` (pixel coordinates), `t` (seconds since start),
+/// `w`, `h` (display size) and `pi`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Expr(Node);
+
+#[derive(Clone, Copy, Debug)]
+enum Var {
+    X,
+    Y,
+    T,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Abs,
+    Sqrt,
+    Floor,
+    Fract,
+    Exp,
+    Min,
+    Max,
+    Pow,
+    Clamp,
+    Mix,
+    Noise,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "tan" => Self::Tan,
+            "abs" => Self::Abs,
+            "sqrt" => Self::Sqrt,
+            "floor" => Self::Floor,
+            "fract" => Self::Fract,
+            "exp" => Self::Exp,
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "pow" => Self::Pow,
+            "clamp" => Self::Clamp,
+            "mix" => Self::Mix,
+            "noise" => Self::Noise,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> std::ops::RangeInclusive<usize> {
+        match self {
+            Self::Sin
+            | Self::Cos
+            | Self::Tan
+            | Self::Abs
+            | Self::Sqrt
+            | Self::Floor
+            | Self::Fract
+            | Self::Exp => 1..=1,
+            Self::Min | Self::Max | Self::Pow => 2..=2,
+            Self::Clamp | Self::Mix => 3..=3,
+            Self::Noise => 2..=3,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Num(f32),
+    Var(Var),
+    Neg(Box<Node>),
+    Bin(BinOp, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+}
+
+impl Node {
+    fn eval(&self, x: f32, y: f32, t: f32) -> f32 {
+        match self {
+            Self::Num(value) => *value,
+            Self::Var(Var::X) => x,
+            Self::Var(Var::Y) => y,
+            Self::Var(Var::T) => t,
+            Self::Neg(node) => -node.eval(x, y, t),
+            Self::Bin(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(x, y, t), rhs.eval(x, y, t));
+                match op {
+                    BinOp::Add => lhs + rhs,
+                    BinOp::Sub => lhs - rhs,
+                    BinOp::Mul => lhs * rhs,
+                    BinOp::Div => lhs / rhs,
+                    BinOp::Rem => lhs.rem_euclid(rhs),
+                    BinOp::Pow => lhs.powf(rhs),
+                }
+            }
+            Self::Call(func, args) => {
+                let arg = |idx: usize| args[idx].eval(x, y, t);
+                match func {
+                    Func::Sin => arg(0).sin(),
+                    Func::Cos => arg(0).cos(),
+                    Func::Tan => arg(0).tan(),
+                    Func::Abs => arg(0).abs(),
+                    Func::Sqrt => arg(0).sqrt(),
+                    Func::Floor => arg(0).floor(),
+                    Func::Fract => arg(0).fract(),
+                    Func::Exp => arg(0).exp(),
+                    Func::Min => arg(0).min(arg(1)),
+                    Func::Max => arg(0).max(arg(1)),
+                    Func::Pow => arg(0).powf(arg(1)),
+                    // not `f32::clamp`, as it panics on bad bounds
+                    Func::Clamp => arg(0).max(arg(1)).min(arg(2)),
+                    Func::Mix => {
+                        let (a, b, k) = (arg(0), arg(1), arg(2));
+                        a + (b - a) * k
+                    }
+                    Func::Noise => {
+                        let z = if args.len() > 2 { arg(2) } else { 0.0 };
+                        noise(arg(0), arg(1), z)
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token<'a> {
+    Num(f32),
+    Ident(&'a str),
+    Op(u8),
+}
+
+fn describe(token: Option<Token<'_>>) -> String {
+    match token {
+        Some(Token::Num(value)) => format!("number `{value}`"),
+        Some(Token::Ident(name)) => format!("`{name}`"),
+        Some(Token::Op(op)) => format!("`{}`", op as char),
+        None => "end of expression".to_owned(),
+    }
+}
+
+fn tokenize(s: &str) -> eyre::Result<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    let bytes = s.as_bytes();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let c = bytes[idx];
+        if c.is_ascii_whitespace() {
+            idx += 1;
+        } else if c.is_ascii_digit() || c == b'.' {
+            let start = idx;
+            while idx < bytes.len() && (bytes[idx].is_ascii_digit() || bytes[idx] == b'.') {
+                idx += 1;
+            }
+            let Ok(value) = f32::from_str(&s[start..idx]) else {
+                bail!("column {}: bad number `{}`", start + 1, &s[start..idx]);
+            };
+            tokens.push(Token::Num(value));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = idx;
+            while idx < bytes.len() && (bytes[idx].is_ascii_alphanumeric() || bytes[idx] == b'_') {
+                idx += 1;
+            }
+            tokens.push(Token::Ident(&s[start..idx]));
+        } else if b"+-*/%^(),".contains(&c) {
+            tokens.push(Token::Op(c));
+            idx += 1;
+        } else {
+            bail!("column {}: unexpected character `{}`", idx + 1, c as char);
+        }
+    }
+    Ok(tokens)
+}
+
+/// Deepest nesting of parentheses, calls and unary operators, so that neither parsing
+/// nor evaluation can overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: u8) -> eyre::Result<()> {
+        match self.bump() {
+            Some(Token::Op(got)) if got == op => Ok(()),
+            other => bail!("expected `{}`, got {}", op as char, describe(other)),
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> eyre::Result<Node> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(b'+')) => BinOp::Add,
+                Some(Token::Op(b'-')) => BinOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> eyre::Result<Node> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(b'*')) => BinOp::Mul,
+                Some(Token::Op(b'/')) => BinOp::Div,
+                Some(Token::Op(b'%')) => BinOp::Rem,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+            lhs = Node::Bin(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    // unary := '-' unary | power
+    fn unary(&mut self) -> eyre::Result<Node> {
+        // every level of nesting passes through here
+        self.depth += 1;
+        ensure!(
+            self.depth <= MAX_DEPTH,
+            "expression is nested more than {MAX_DEPTH} levels deep"
+        );
+        let node = if self.peek() == Some(Token::Op(b'-')) {
+            self.bump();
+            self.unary().map(|node| Node::Neg(Box::new(node)))
+        } else {
+            self.power()
+        };
+        self.depth -= 1;
+        node
+    }
+
+    // power := atom ('^' unary)?
+    fn power(&mut self) -> eyre::Result<Node> {
+        let base = self.atom()?;
+        if self.peek() == Some(Token::Op(b'^')) {
+            self.bump();
+            return Ok(Node::Bin(
+                BinOp::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> eyre::Result<Node> {
+        match self.bump() {
+            Some(Token::Num(value)) => Ok(Node::Num(value)),
+            Some(Token::Op(b'(')) => {
+                let node = self.expr()?;
+                self.expect(b')')?;
+                Ok(node)
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(Token::Op(b'(')) => {
+                let func =
+                    Func::from_name(name).ok_or_else(|| eyre!("unknown function `{name}`"))?;
+                self.bump();
+                let mut args = Vec::new();
+                if self.peek() != Some(Token::Op(b')')) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.peek() != Some(Token::Op(b',')) {
+                            break;
+                        }
+                        self.bump();
+                    }
+                }
+                self.expect(b')')?;
+                ensure!(
+                    func.arity().contains(&args.len()),
+                    "wrong number of arguments for `{name}`: {}",
+                    args.len()
+                );
+                Ok(Node::Call(func, args))
+            }
+            Some(Token::Ident(name)) => Ok(match name {
+                "x" => Node::Var(Var::X),
+                "y" => Node::Var(Var::Y),
+                "t" => Node::Var(Var::T),
+                "w" => Node::Num(9.0),
+                "h" => Node::Num(34.0),
+                "pi" => Node::Num(PI),
+                _ => bail!("unknown variable `{name}`"),
+            }),
+            other => bail!("unexpected {}", describe(other)),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            depth: 0,
+        };
+        let node = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            bail!(
+                "unexpected {} after the end of expression",
+                describe(Some(token))
+            );
+        }
+        Ok(Self(node))
+    }
+}
+
+impl TryFrom<String> for Expr {
+    type Error = eyre::Report;
+
+    fn try_from(value: String) -> eyre::Result<Self> {
+        value
+            .parse()
+            .map_err(|err: eyre::Report| err.wrap_err(format!("bad expression `{value}`")))
+    }
+}
+
+impl Expr {
+    pub fn render(&self, t: f32) -> GrayFrame {
+        let mut frame = GrayFrame::default();
+        for (x, column) in frame.0.iter_mut().enumerate() {
+            for (y, pixel) in column.iter_mut().enumerate() {
+                let value = self.0.eval(x as f32, y as f32, t);
+                // NaN becomes 0, as float to int casts saturate
+                *pixel = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+        frame
+    }
+}
+
+fn hash(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8DA6_B343)
+        ^ (y as u32).wrapping_mul(0xD816_3841)
+        ^ (z as u32).wrapping_mul(0xCB1A_B31F);
+    h = (h ^ (h >> 13)).wrapping_mul(0x5BD1_E995);
+    h ^= h >> 15;
+    (h & 0xFF_FFFF) as f32 / 0xFF_FFFF as f32
+}
+
+/// Smooth value noise in `0.0..=1.0`.
+fn noise(x: f32, y: f32, z: f32) -> f32 {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let smooth = |v: f32| v * v * (3.0 - 2.0 * v);
+    let (fx, fy, fz) = (smooth(x - x0), smooth(y - y0), smooth(z - z0));
+    // saturates for huge inputs, neighbours wrap around then
+    let (ix, iy, iz) = (x0 as i32, y0 as i32, z0 as i32);
+    let lerp = |a: f32, b: f32, k: f32| a + (b - a) * k;
+
+    let plane = |iz: i32| {
+        lerp(
+            lerp(hash(ix, iy, iz), hash(ix.wrapping_add(1), iy, iz), fx),
+            lerp(
+                hash(ix, iy.wrapping_add(1), iz),
+                hash(ix.wrapping_add(1), iy.wrapping_add(1), iz),
+                fx,
+            ),
+            fy,
+        )
+    };
+    lerp(plane(iz), plane(iz.wrapping_add(1)), fz)
+}
+
+#[derive(Clone)]
+pub struct Shader {
+    expr: Expr,
+    frame_duration: Duration,
+    duration: Duration,
+    /// Time of the next frame.
+    time: Duration,
+}
+
+impl Shader {
+    pub fn from_config(config: &config::ShaderAnimation) -> Self {
+        Self {
+            expr: config.expression.clone(),
+            frame_duration: config.frame_duration,
+            duration: config.duration,
+            time: Duration::ZERO,
+        }
+    }
+}
+
+impl Iterator for Shader {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // time advances by whole frames, so rendering is deterministic
+        let t = self.time;
+        if t >= self.duration {
+            return None;
+        }
+        self.time = self.time.saturating_add(self.frame_duration);
+
+        Some(Frame {
+            data: FrameData::Gray(self.expr.render(t.as_secs_f32())),
+            min_duration: self.frame_duration,
+            fullscreen: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(s: &str) -> f32 {
+        s.parse::<Expr>().unwrap().0.eval(2.0, 3.0, 0.5)
+    }
+
+    fn error(s: &str) -> String {
+        s.parse::<Expr>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("10 - 4 - 3"), 3.0);
+        assert_eq!(eval("2 * 3 ^ 2"), 18.0);
+        assert_eq!(eval("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(eval("7 % 4 * 2"), 6.0);
+        assert_eq!(eval("x * w + y"), 21.0);
+        assert_eq!(eval("t"), 0.5);
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("-2 ^ 2"), -4.0);
+        assert_eq!(eval("2 ^ -1"), 0.5);
+        assert_eq!(eval("--x"), 2.0);
+        assert_eq!(eval("1 - -y"), 4.0);
+        assert_eq!(eval("-1 % 3"), 2.0);
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("max(x, y)"), 3.0);
+        assert_eq!(eval("clamp(5, 0, 1)"), 1.0);
+        assert_eq!(eval("mix(0, 10, t)"), 5.0);
+        assert_eq!(error("sin(1, 2)"), "wrong number of arguments for `sin`: 2");
+        assert_eq!(error("min(1)"), "wrong number of arguments for `min`: 1");
+        assert_eq!(error("noise()"), "wrong number of arguments for `noise`: 0");
+        assert_eq!(eval("noise(1, 2, 3)"), noise(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn bad_expressions() {
+        assert_eq!(error("x + z"), "unknown variable `z`");
+        assert_eq!(error("tanh(x)"), "unknown function `tanh`");
+        assert_eq!(error("x +"), "unexpected end of expression");
+        assert_eq!(error("(x"), "expected `)`, got end of expression");
+        assert_eq!(error("x y"), "unexpected `y` after the end of expression");
+        assert_eq!(error("x $ 2"), "column 3: unexpected character `$`");
+        assert_eq!(error("1.2.3"), "column 1: bad number `1.2.3`");
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth| format!("{}x{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(MAX_DEPTH - 1)), 2.0);
+        let message = format!("expression is nested more than {MAX_DEPTH} levels deep");
+        assert_eq!(error(&nested(100_000)), message);
+        assert_eq!(error(&format!("{}x", "-".repeat(100_000))), message);
+        assert_eq!(
+            error(&format!(
+                "{}x{}",
+                "sin(".repeat(100_000),
+                ")".repeat(100_000)
+            )),
+            message
+        );
+    }
+
+    #[test]
+    fn render_clamps() {
+        let frame = "x / 4 - 0.5".parse::<Expr>().unwrap().render(0.0);
+        let row: Vec<_> = frame.0.iter().map(|column| column[0]).collect();
+        assert_eq!(row, [0, 0, 0, 64, 128, 191, 255, 255, 255]);
+        let frame = "0 / 0".parse::<Expr>().unwrap().render(0.0);
+        assert_eq!(frame.0[0][0], 0);
+    }
+
+    #[test]
+    fn noise_is_deterministic() {
+        for (x, y, z) in [(0.5, 1.5, 0.0), (3.25, -7.75, 2.5), (1e30, -1e30, 1e30)] {
+            let value = noise(x, y, z);
+            assert_eq!(value, noise(x, y, z));
+            assert!((0.0..=1.0).contains(&value));
+        }
+        // lattice points are the hash itself, the same for every run
+        assert_eq!(noise(1.0, 2.0, 3.0), hash(1, 2, 3));
+        assert_ne!(noise(0.0, 0.0, 0.0), noise(1.0, 0.0, 0.0));
+    }
+}
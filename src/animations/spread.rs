@@ -1,10 +1,20 @@
-use std::{collections::HashMap, time::Duration};
+use std::time::Duration;
 
 use crate::{
     animations::{Animation, Frame, FrameData, GrayFrame},
-    config,
+    config::{self, Decay, Edges, ScheduledSeed},
+    rng::Rng,
 };
 
+/// Seeds requested at play time instead of the configured ones.
+#[derive(Clone)]
+pub enum Seeds {
+    /// `[x, y, brightness]`, offset the same way as configured seeds.
+    Explicit(Vec<[u8; 3]>),
+    /// `count` seeds at random positions of the whole display.
+    Random { count: usize, rng_seed: u64 },
+}
+
 pub enum Diagonals {
     Yes,
     No,
@@ -12,61 +22,144 @@ pub enum Diagonals {
 }
 
 pub struct Spread<F> {
-    // inefficient, but who cares
-    current_set: HashMap<(u8, u8), u8>,
-    buffer: HashMap<(u8, u8), u8>,
+    current: [[u8; 34]; 9],
+    buffer: [[u8; 34]; 9],
     duration: Duration,
     step: F,
+    decay: Decay,
+    edges: Edges,
+    generation: usize,
+    max_generations: Option<usize>,
+    // sorted by frame in reverse, so the next one to inject is at the end
+    scheduled: Vec<ScheduledSeed>,
 }
 
-pub fn from_config_at(config: config::SpreadAnimation, offset: i8) -> Animation {
+pub fn from_config_at(
+    config: config::SpreadAnimation,
+    offset: i8,
+    seeds: Option<&Seeds>,
+) -> Animation {
     let mut result = Spread::new(config.frame_duration, move |dy, dx| match (dy, dx) {
         (0, 0) => config.stay_cost,
         (_, 0) => config.horiz_cost,
         (0, _) => config.vert_cost,
         _ => config.diag_cost,
     });
+    result.decay = config.decay;
+    result.edges = config.edges;
+    result.max_generations = config.max_generations;
 
-    for [x, y, brightness] in config.seeds {
-        let y = if offset < 0 {
-            y.checked_sub((-offset) as u8)
-        } else {
-            y.checked_add(offset as u8).filter(|&y| y < 34)
-        };
+    let seeds = match seeds {
+        None => config.seeds,
+        Some(Seeds::Explicit(seeds)) => seeds.clone(),
+        Some(&Seeds::Random { count, rng_seed }) => {
+            let mut rng = Rng::new(rng_seed);
+            let [min, max] = config.random_brightness;
+            for _ in 0..count {
+                let x = rng.below(9) as u8;
+                let y = rng.below(34) as u8;
+                let brightness = min + rng.below(u64::from(max.saturating_sub(min)) + 1) as u8;
+                result.set(y, x, brightness);
+            }
+            Vec::new()
+        }
+    };
+
+    for [x, y, brightness] in seeds {
         // out of bounds, ignore
-        let Some(y) = y else {
+        let Some(y) = offset_y(y, offset) else {
             continue;
         };
+        if x > 8 {
+            continue;
+        }
         result.set(y, x, brightness);
     }
 
+    for seed in config.scheduled_seeds {
+        let Some(y) = offset_y(seed.y, offset) else {
+            continue;
+        };
+        result.schedule(ScheduledSeed { y, ..seed });
+    }
+
     Box::new(result)
 }
 
+fn offset_y(y: u8, offset: i8) -> Option<u8> {
+    if offset < 0 {
+        y.checked_sub((-offset) as u8)
+    } else {
+        y.checked_add(offset as u8).filter(|&y| y < 34)
+    }
+}
+
 impl<F> Spread<F> {
     pub fn new(duration: Duration, step: F) -> Self {
         Self {
-            current_set: HashMap::with_capacity(34 * 9),
-            buffer: HashMap::with_capacity(34 * 9),
+            current: [[0; 34]; 9],
+            buffer: [[0; 34]; 9],
             duration,
             step,
+            decay: Decay::Linear,
+            edges: Edges::Bounded,
+            generation: 0,
+            max_generations: None,
+            scheduled: Vec::new(),
         }
     }
 
     pub fn set(&mut self, y: u8, x: u8, brightness: u8) {
-        self.current_set.insert((y, x), brightness);
+        let cell = &mut self.current[x as usize][y as usize];
+        *cell = (*cell).max(brightness);
+    }
+
+    /// Adds a seed that appears at the given frame instead of the first one.
+    pub fn schedule(&mut self, seed: ScheduledSeed) {
+        let idx = self
+            .scheduled
+            .partition_point(|other| other.frame > seed.frame);
+        self.scheduled.insert(idx, seed);
     }
 
     pub fn is_empty(&self) -> bool {
-        self.current_set.is_empty()
+        self.current
+            .iter()
+            .flatten()
+            .all(|&brightness| brightness == 0)
     }
 
     pub fn to_frame(&self) -> GrayFrame {
-        let mut frame = GrayFrame::default();
-        for (&(y, x), &brightness) in &self.current_set {
-            frame.0[x as usize][y as usize] = brightness;
+        GrayFrame(self.current)
+    }
+
+    fn inject_scheduled(&mut self) {
+        while let Some(seed) = self.scheduled.last() {
+            if seed.frame > self.generation {
+                break;
+            }
+            let ScheduledSeed {
+                x, y, brightness, ..
+            } = *seed;
+            self.scheduled.pop();
+            self.set(y, x, brightness);
+        }
+    }
+
+    fn decayed(&self, brightness: u8, cost: u8) -> u8 {
+        match self.decay {
+            Decay::Linear => brightness.saturating_sub(cost),
+            Decay::Multiplicative => {
+                // cast is safe, as the result is never bigger than `brightness`
+                (u16::from(brightness) * (256 - u16::from(cost)) / 256) as u8
+            }
+            Decay::Exponential { growth } => {
+                let generation = i32::try_from(self.generation).unwrap_or(i32::MAX);
+                let cost = f32::from(cost) * growth.powi(generation);
+                // float to int casts saturate
+                (f32::from(brightness) - cost) as u8
+            }
         }
-        frame
     }
 }
 
@@ -77,7 +170,14 @@ where
     type Item = Frame;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.is_empty() {
+        self.inject_scheduled();
+        if self.is_empty() && self.scheduled.is_empty() {
+            return None;
+        }
+        if self
+            .max_generations
+            .is_some_and(|max| self.generation >= max)
+        {
             return None;
         }
 
@@ -86,31 +186,153 @@ where
             min_duration: self.duration,
             fullscreen: false,
         };
-        self.buffer.clear();
-        // terrible algo, but again, who cares
-        for (&(y, x), &brightness) in &self.current_set {
-            let sx = x.saturating_sub(1);
-            let sy = y.saturating_sub(1);
-            let ex = x + 1;
-            let ey = y + 1;
-            for nx in sx..=ex {
-                for ny in sy..=ey {
-                    if !(0..=33).contains(&ny) || !(0..=8).contains(&nx) {
-                        continue;
-                    }
+        self.buffer = [[0; 34]; 9];
+        for x in 0..9i8 {
+            for y in 0..34i8 {
+                let brightness = self.current[x as usize][y as usize];
+                if brightness == 0 {
+                    continue;
+                }
 
-                    let step = (self.step)(ny as i8 - y as i8, nx as i8 - x as i8);
+                for dx in -1..=1 {
+                    for dy in -1..=1 {
+                        let (nx, ny) = match self.edges {
+                            Edges::Toroidal => ((x + dx).rem_euclid(9), (y + dy).rem_euclid(34)),
+                            Edges::Bounded => (x + dx, y + dy),
+                        };
+                        if !(0..=33).contains(&ny) || !(0..=8).contains(&nx) {
+                            continue;
+                        }
 
-                    if brightness <= step {
-                        continue;
-                    }
+                        let step = (self.step)(dy, dx);
+                        let brightness = self.decayed(brightness, step);
+                        if brightness == 0 {
+                            continue;
+                        }
 
-                    let cell = self.buffer.entry((ny, nx)).or_default();
-                    *cell = (*cell).max(brightness - step);
+                        let cell = &mut self.buffer[nx as usize][ny as usize];
+                        *cell = (*cell).max(brightness);
+                    }
                 }
             }
         }
-        std::mem::swap(&mut self.current_set, &mut self.buffer);
+        std::mem::swap(&mut self.current, &mut self.buffer);
+        self.generation += 1;
         Some(frame)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A spread with a seed of 100 in the middle; steps cost 1 to stay, 10 vertically,
+    /// 20 horizontally and 30 diagonally.
+    fn spread(decay: Decay, edges: Edges, seed: (u8, u8)) -> Spread<impl FnMut(i8, i8) -> u8> {
+        let mut spread = Spread::new(Duration::from_millis(50), |dy, dx| match (dy, dx) {
+            (0, 0) => 1,
+            (_, 0) => 10,
+            (0, _) => 20,
+            _ => 30,
+        });
+        spread.decay = decay;
+        spread.edges = edges;
+        let (x, y) = seed;
+        spread.set(y, x, 100);
+        spread
+    }
+
+    fn gray(frame: Option<Frame>) -> GrayFrame {
+        match frame.expect("spread ended early").data {
+            FrameData::Gray(frame) => frame,
+            _ => panic!("spreads should make grayscale frames"),
+        }
+    }
+
+    /// The middle cell and its neighbours above, to the left and diagonally.
+    fn around_middle(frame: &GrayFrame) -> [u8; 4] {
+        [
+            frame.0[4][17],
+            frame.0[4][16],
+            frame.0[3][17],
+            frame.0[3][16],
+        ]
+    }
+
+    #[test]
+    fn linear_decay() {
+        let mut spread = spread(Decay::Linear, Edges::Bounded, (4, 17));
+        assert_eq!(around_middle(&gray(spread.next())), [100, 0, 0, 0]);
+        assert_eq!(around_middle(&gray(spread.next())), [99, 90, 80, 70]);
+        assert_eq!(gray(spread.next()).0[4][17], 98);
+    }
+
+    #[test]
+    fn multiplicative_decay() {
+        let mut spread = spread(Decay::Multiplicative, Edges::Bounded, (4, 17));
+        spread.next();
+        // brightness * (256 - cost) / 256, rounded down
+        assert_eq!(around_middle(&gray(spread.next())), [99, 96, 92, 88]);
+    }
+
+    #[test]
+    fn exponential_decay() {
+        let mut spread = spread(Decay::Exponential { growth: 2. }, Edges::Bounded, (4, 17));
+        spread.next();
+        // costs double every generation, starting from the configured ones
+        assert_eq!(around_middle(&gray(spread.next())), [99, 90, 80, 70]);
+        assert_eq!(gray(spread.next()).0[4][17], 97);
+        assert_eq!(gray(spread.next()).0[4][17], 93);
+    }
+
+    #[test]
+    fn edges() {
+        let mut bounded = spread(Decay::Linear, Edges::Bounded, (0, 0));
+        bounded.next();
+        let frame = gray(bounded.next());
+        assert_eq!(
+            [frame.0[0][0], frame.0[0][1], frame.0[1][0], frame.0[1][1]],
+            [99, 90, 80, 70]
+        );
+        assert_eq!([frame.0[8][0], frame.0[0][33], frame.0[8][33]], [0, 0, 0]);
+
+        let mut toroidal = spread(Decay::Linear, Edges::Toroidal, (0, 0));
+        toroidal.next();
+        let frame = gray(toroidal.next());
+        assert_eq!(
+            [frame.0[8][0], frame.0[0][33], frame.0[8][33]],
+            [80, 90, 70]
+        );
+    }
+
+    #[test]
+    fn max_generations() {
+        let mut endless = Spread::new(Duration::from_millis(50), |_, _| 0);
+        endless.set(17, 4, 100);
+        endless.max_generations = Some(3);
+        assert_eq!(endless.count(), 3);
+    }
+
+    #[test]
+    fn scheduled_seeds() {
+        let config: config::SpreadAnimation = toml::from_str(
+            "seeds = []\nframe_duration = \"50ms\"\n\
+             stay_cost = 255\nhoriz_cost = 255\nvert_cost = 255\ndiag_cost = 255\n\
+             scheduled_seeds = [{ frame = 2, x = 4, y = 10, brightness = 200 }]\n",
+        )
+        .unwrap();
+        let frames: Vec<_> = from_config_at(config, 2, None)
+            .map(|frame| gray(Some(frame)))
+            .collect();
+        // empty frames until the seed appears, offset like configured seeds, then it's gone
+        assert_eq!(frames.len(), 3);
+        assert!(
+            frames[..2]
+                .iter()
+                .all(|frame| frame.0 == GrayFrame::default().0)
+        );
+        let mut expected = GrayFrame::default();
+        expected.0[4][12] = 200;
+        assert_eq!(frames[2].0, expected.0);
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    sync::{Mutex, mpsc},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    animations::{Animation, Frame, FrameData, GrayFrame},
+    proto::BwFrame,
+};
+
+/// Size of a binary frame: brightness of every LED, column by column like [`GrayFrame`].
+pub const BINARY_FRAME_SIZE: usize = 9 * 34;
+
+/// Time without frames after which the layer is removed, unless requested otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often the latest frame is shown, so new ones appear quickly.
+const FRAME_DURATION: Duration = Duration::from_millis(20);
+
+/// A layer showing the latest frame sent by a client.
+pub struct Stream {
+    // only for `Sync`, never contended
+    frames: Mutex<mpsc::Receiver<FrameData>>,
+    current: FrameData,
+    last_update: Instant,
+    timeout: Duration,
+}
+
+/// Starts a layer which ends once no frame was sent for `timeout`, or the sender is dropped.
+pub fn start(timeout: Duration) -> (mpsc::Sender<FrameData>, Animation) {
+    let (sender, frames) = mpsc::channel();
+    let stream = Stream {
+        frames: Mutex::new(frames),
+        current: FrameData::Bw(BwFrame::default()),
+        last_update: Instant::now(),
+        timeout,
+    };
+    (sender, Box::new(stream))
+}
+
+pub fn from_binary(bytes: &[u8; BINARY_FRAME_SIZE]) -> FrameData {
+    let mut frame = GrayFrame::default();
+    for (column, bytes) in frame.0.iter_mut().zip(bytes.chunks_exact(34)) {
+        column.copy_from_slice(bytes);
+    }
+    FrameData::Gray(frame)
+}
+
+impl Iterator for Stream {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let frames = self.frames.get_mut().unwrap();
+        loop {
+            match frames.try_recv() {
+                Ok(frame) => {
+                    self.current = frame;
+                    self.last_update = Instant::now();
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return None,
+            }
+        }
+        if self.last_update.elapsed() > self.timeout {
+            return None;
+        }
+        Some(Frame {
+            data: self.current.clone(),
+            min_duration: FRAME_DURATION,
+            fullscreen: false,
+        })
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    collections::VecDeque,
+    io::{BufRead as _, BufReader, Write as _},
+    os::unix::net::UnixStream,
+    path::Path,
+};
+
+use eyre::{WrapErr as _, bail, eyre};
+
+use crate::daemon::protocol::{Event, PROTOCOL_VERSION, Reply, ReplyLine, Request, RequestLine};
+
+/// Connection to the daemon speaking the JSON protocol.
+pub struct Client {
+    stream: BufReader<UnixStream>,
+    next_id: u64,
+    /// Events received while waiting for a reply.
+    events: VecDeque<Event>,
+    /// Protocol version negotiated with the daemon.
+    pub version: u32,
+}
+
+impl Client {
+    pub fn connect(socket_path: &Path) -> eyre::Result<Self> {
+        let stream = UnixStream::connect(socket_path).wrap_err_with(|| {
+            format!("failed to connect to daemon at `{}`", socket_path.display())
+        })?;
+        let mut client = Self {
+            stream: BufReader::new(stream),
+            next_id: 0,
+            events: VecDeque::new(),
+            version: 0,
+        };
+        let hello = client.request(Request::Hello {
+            version: PROTOCOL_VERSION,
+        })??;
+        client.version = hello["version"]
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| eyre!("daemon didn't report its protocol version"))?;
+        Ok(client)
+    }
+
+    /// Sends a request; the outer error is about the connection, the inner one is
+    /// the daemon refusing the request.
+    pub fn request(
+        &mut self,
+        request: Request,
+    ) -> eyre::Result<Result<serde_json::Value, crate::daemon::protocol::Error>> {
+        self.next_id += 1;
+        let id = serde_json::Value::from(self.next_id);
+        let line = request_line(&id, request)?;
+        self.stream.get_mut().write_all(line.as_bytes())?;
+
+        let reply = loop {
+            let line = self.read_line()?;
+            let value: serde_json::Value = serde_json::from_str(&line)
+                .wrap_err_with(|| format!("bad reply from daemon: {line:?}"))?;
+            if value.get("event").is_some() {
+                self.events.push_back(serde_json::from_value(value)?);
+                continue;
+            }
+            let reply: ReplyLine = serde_json::from_value(value)
+                .wrap_err_with(|| format!("bad reply from daemon: {line:?}"))?;
+            break reply;
+        };
+        if reply.id.as_ref() != Some(&id) {
+            bail!("daemon replied to another request: {reply:?}");
+        }
+        Ok(match reply.reply {
+            Reply::Ok(result) => Ok(result),
+            Reply::Error(err) => Err(err),
+        })
+    }
+
+    /// Waits for the next event; only useful after a `subscribe` request.
+    pub fn next_event(&mut self) -> eyre::Result<Event> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        let line = self.read_line()?;
+        serde_json::from_str(&line).wrap_err_with(|| format!("bad event from daemon: {line:?}"))
+    }
+
+    fn read_line(&mut self) -> eyre::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line)? == 0 {
+            bail!("daemon closed the connection");
+        }
+        Ok(line)
+    }
+}
+
+/// Serializes a request as a line of the JSON protocol.
+fn request_line(id: &serde_json::Value, request: Request) -> serde_json::Result<String> {
+    let mut line = serde_json::to_string(&RequestLine {
+        id: Some(id.clone()),
+        request,
+    })?;
+    line.push('\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::daemon::protocol::RandomSeeds;
+
+    #[test]
+    fn requests_round_trip() {
+        let requests = [
+            Request::Hello { version: 1 },
+            Request::Charger,
+            Request::Play {
+                animation: "spread".to_owned(),
+                display: "left".to_owned(),
+                offset: Some(-3),
+                seeds: Some(vec![[1, 2, 255]]),
+                random: Some(RandomSeeds {
+                    count: 5,
+                    rng_seed: Some(42),
+                }),
+                wait: true,
+            },
+            Request::Play {
+                animation: "spread".to_owned(),
+                display: "left".to_owned(),
+                offset: None,
+                seeds: None,
+                random: None,
+                wait: false,
+            },
+            Request::ProgressStart {
+                id: "build".to_owned(),
+                display: "left".to_owned(),
+                style: Some("bar".to_owned()),
+            },
+            Request::ProgressSet {
+                id: "build".to_owned(),
+                value: 50,
+            },
+            Request::ProgressDone {
+                id: "build".to_owned(),
+            },
+            Request::Stop {
+                display: "left".to_owned(),
+            },
+            Request::Brightness {
+                display: "left".to_owned(),
+                value: 40,
+            },
+            Request::Reload,
+            Request::ListDisplays,
+            Request::ListAnimations,
+            Request::Status { display: None },
+            Request::Status {
+                display: Some("left".to_owned()),
+            },
+            Request::Version,
+            Request::Subscribe,
+            Request::Stream {
+                display: "left".to_owned(),
+                binary: true,
+                timeout: Some(Duration::from_millis(1500)),
+            },
+            Request::Define {
+                name: "pulse".to_owned(),
+                body: "---\n".to_owned(),
+            },
+            Request::Undefine {
+                name: "pulse".to_owned(),
+            },
+        ];
+        for (id, request) in requests.into_iter().enumerate() {
+            let id = serde_json::Value::from(id);
+            let line = request_line(&id, request.clone()).unwrap();
+            let parsed = RequestLine::parse(&line).unwrap();
+            assert_eq!(parsed.id, Some(id), "{line}");
+            assert_eq!(parsed.request, request, "{line}");
+        }
+    }
+}
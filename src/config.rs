@@ -1,56 +1,471 @@
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use eyre::ensure;
+use eyre::{WrapErr as _, bail};
 use serde::Deserialize;
 
+use crate::animations::{
+    self,
+    automaton::{Automaton, Rule},
+    shader::Expr,
+};
+
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub displays: HashMap<String, String>,
+    pub displays: HashMap<String, DisplayConfig>,
     #[serde(default = "default_socket_path")]
     pub socket_path: PathBuf,
+    /// Reload automatically when the config or animation files change.
+    #[serde(default)]
+    pub watch: bool,
 
     #[serde(default)]
     pub builtin: BuiltinConfig,
     #[serde(default)]
     pub animations: HashMap<String, AnimationConfig>,
+    /// Directories whose animation files are registered under their file stem.
+    #[serde(default)]
+    pub animation_dirs: Vec<AnimationDir>,
+    /// Where animations defined over the control socket are kept across restarts; they
+    /// are forgotten on exit if unset.
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+
+    /// Animations found in `animation_dirs`, moved to `animations` once validated.
+    #[serde(skip)]
+    pub discovered: Vec<DiscoveredAnimation>,
+    /// Descriptions of animations, from animation directory manifests.
+    #[serde(skip)]
+    pub descriptions: HashMap<String, String>,
 }
 
-fn default_socket_path() -> PathBuf {
+pub fn default_socket_path() -> PathBuf {
     "/run/fw-lights.sock".into()
 }
 
+/// Config used when none is passed: `$XDG_CONFIG_HOME/fw-lights.toml` (or
+/// `~/.config/fw-lights.toml`), then `/etc/fw-lights.toml`.
+pub fn find_default() -> eyre::Result<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    let candidates: Vec<_> = config_home
+        .map(|dir| dir.join("fw-lights.toml"))
+        .into_iter()
+        .chain([PathBuf::from("/etc/fw-lights.toml")])
+        .collect();
+    match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => bail!(
+            "no config found, tried {}; pass one with `--config`",
+            candidates
+                .iter()
+                .map(|path| format!("`{}`", path.display()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
 impl Config {
-    pub fn validate(&self) -> eyre::Result<()> {
+    /// Reads, parses and validates a config file.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let mut config = Self::read(path)?;
+        config.validate()?;
+        config.register_discovered();
+        Ok(config)
+    }
+
+    /// Reads and parses a config file and discovers animations, leaving validation and
+    /// [`Self::register_discovered`] to the caller.
+    pub fn read(path: &Path) -> eyre::Result<Self> {
+        let raw_config = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read config `{}`", path.display()))?;
+        let mut config: Self = toml::from_str(&raw_config)
+            .wrap_err_with(|| format!("failed to parse config `{}`", path.display()))?;
+        config.discover_animations()?;
+        Ok(config)
+    }
+
+    /// Moves validated animations from `discovered` to `animations`.
+    pub fn register_discovered(&mut self) {
+        for animation in std::mem::take(&mut self.discovered) {
+            if let Some(description) = animation.description {
+                self.descriptions
+                    .insert(animation.name.clone(), description);
+            }
+            self.animations.insert(animation.name, animation.config);
+        }
+    }
+
+    /// Fills `discovered` from `animation_dirs`.
+    pub fn discover_animations(&mut self) -> eyre::Result<()> {
+        for dir in &self.animation_dirs {
+            let manifest_path = dir.manifest_path();
+            let mut manifest: HashMap<String, toml::Table> =
+                match fs::read_to_string(&manifest_path) {
+                    Ok(raw) => toml::from_str(&raw).wrap_err_with(|| {
+                        format!("failed to parse manifest `{}`", manifest_path.display())
+                    })?,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound && dir.manifest.is_none() => {
+                        HashMap::new()
+                    }
+                    Err(err) => {
+                        return Err(err).wrap_err_with(|| {
+                            format!("failed to read manifest `{}`", manifest_path.display())
+                        });
+                    }
+                };
+
+            let mut paths = fs::read_dir(&dir.path)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| entry.map(|entry| entry.path()))
+                        .collect::<io::Result<Vec<_>>>()
+                })
+                .wrap_err_with(|| {
+                    format!(
+                        "failed to read animation directory `{}`",
+                        dir.path.display()
+                    )
+                })?;
+            // for stable error messages
+            paths.sort();
+
+            for path in paths {
+                let (Some(name), Some(extension)) = (
+                    path.file_stem().and_then(OsStr::to_str),
+                    path.extension().and_then(OsStr::to_str),
+                ) else {
+                    continue;
+                };
+                let path_value = toml::Value::try_from(&path)?;
+                let mut table = toml::Table::new();
+                match extension {
+                    "anim" => {
+                        table.insert("kind".into(), "file".into());
+                        table.insert("path".into(), path_value);
+                    }
+                    "rhai" => {
+                        table.insert("kind".into(), "script".into());
+                        table.insert("path".into(), path_value);
+                    }
+                    "png" | "gif" => {
+                        table.insert("kind".into(), "image".into());
+                        table.insert("paths".into(), vec![path_value].into());
+                    }
+                    _ => continue,
+                }
+
+                let mut description = None;
+                for (key, value) in manifest.remove(name).unwrap_or_default() {
+                    if key == "description" {
+                        let Some(value) = value.as_str() else {
+                            bail!(
+                                "description of `{name}` in `{}` must be a string",
+                                manifest_path.display()
+                            );
+                        };
+                        description = Some(value.to_owned());
+                    } else {
+                        table.insert(key, value);
+                    }
+                }
+                let config = toml::Value::Table(table).try_into().wrap_err_with(|| {
+                    format!(
+                        "failed to configure animation `{name}` from `{}`",
+                        path.display()
+                    )
+                })?;
+                self.discovered.push(DiscoveredAnimation {
+                    name: name.to_owned(),
+                    path,
+                    config,
+                    description,
+                });
+            }
+
+            if let Some(name) = manifest.keys().next() {
+                bail!(
+                    "manifest `{}` describes `{name}`, which has no animation file",
+                    manifest_path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Files which are read when building animations, for watching them.
+    pub fn animation_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for animation in self.animations.values() {
+            match animation {
+                AnimationConfig::File(file) => files.push(file.path.clone()),
+                AnimationConfig::Script(script) => files.push(script.path.clone()),
+                AnimationConfig::Image(image) => files.extend(image.paths.iter().cloned()),
+                AnimationConfig::Builtin(BuiltinAnimation::Automaton(AutomatonAnimation {
+                    seed: AutomatonSeed::File { path, .. },
+                    ..
+                })) => files.push(path.clone()),
+                AnimationConfig::Builtin(_) => {}
+            }
+        }
+        for dir in &self.animation_dirs {
+            files.push(dir.manifest_path());
+        }
+        files
+    }
+
+    /// Checks everything that doesn't depend on the machine, reporting all problems at
+    /// once.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = ConfigError::default();
+
+        let mut discovered = HashSet::new();
+        for animation in &self.discovered {
+            let path = format!("{}", animation.path.display());
+            if self.animations.contains_key(&animation.name) {
+                errors.push(
+                    &path,
+                    format!(
+                        "animation `{}` is already configured in `animations`",
+                        animation.name
+                    ),
+                );
+            }
+            if !discovered.insert(&animation.name) {
+                errors.push(
+                    &path,
+                    format!(
+                        "animation `{}` was already found in animation directories",
+                        animation.name
+                    ),
+                );
+            }
+        }
+
+        let mut animations: Vec<_> = self
+            .animations
+            .iter()
+            .map(|(name, animation)| (format!("animations.{name}"), animation))
+            .chain(self.discovered.iter().map(|animation| {
+                (
+                    format!("animation_dirs.{}", animation.name),
+                    &animation.config,
+                )
+            }))
+            .collect();
+        // for stable error messages
+        animations.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (path, animation) in animations {
+            animation.validate(&path, &mut errors);
+        }
+
         if let Some(charger) = &self.builtin.charger {
-            for animation in [&charger.animation_left, &charger.animation_right] {
-                ensure!(
-                    self.animations.contains_key(animation),
-                    "animation `{}` specified for `builtin.charger` does not exist",
-                    animation
+            for (key, animation) in [
+                ("animation_left", &charger.animation_left),
+                ("animation_right", &charger.animation_right),
+            ] {
+                if !self.animations.contains_key(animation) && !discovered.contains(animation) {
+                    errors.push(
+                        format!("builtin.charger.{key}"),
+                        format!("animation `{animation}` does not exist"),
+                    );
+                }
+            }
+            for (key, display) in [
+                ("left_display", &charger.left_display),
+                ("right_display", &charger.right_display),
+            ] {
+                if !self.displays.contains_key(display) {
+                    errors.push(
+                        format!("builtin.charger.{key}"),
+                        format!("display `{display}` does not exist"),
+                    );
+                }
+            }
+        }
+
+        let mut styles: Vec<_> = self.builtin.progress.iter().collect();
+        styles.sort_by_key(|(name, _)| *name);
+        for (name, style) in styles {
+            if style.frame_duration.is_zero() {
+                errors.push(
+                    format!("builtin.progress.{name}.frame_duration"),
+                    "must not be zero",
                 );
             }
-            ensure!(
-                self.displays.contains_key(&charger.left_display),
-                "display `{}` specified for `builtin.charger does not exist",
-                charger.left_display
-            );
-            ensure!(
-                self.displays.contains_key(&charger.right_display),
-                "display `{}` specified for `builtin.charger does not exist",
-                charger.right_display
-            );
+        }
+
+        errors.into_result()
+    }
+
+    /// Checks that display devices exist, suggesting serial ports which do.
+    pub fn validate_displays(&self) -> Result<(), ConfigError> {
+        let mut errors = ConfigError::default();
+        let mut names: Vec<_> = self.displays.keys().collect();
+        names.sort();
+        for name in names {
+            let path = &self.displays[name].path;
+            if Path::new(path).exists() {
+                continue;
+            }
+            let ports: Vec<_> = serialport::available_ports()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|port| format!("`{}`", port.port_name))
+                .collect();
+            let message = if ports.is_empty() {
+                format!("device `{path}` does not exist and no serial ports were found")
+            } else {
+                format!(
+                    "device `{path}` does not exist; available serial ports: {}",
+                    ports.join(", ")
+                )
+            };
+            errors.push(format!("displays.{name}"), message);
+        }
+        errors.into_result()
+    }
+}
+
+/// Problems found in a config, each with the key or file it's about.
+#[derive(Debug, Default)]
+pub struct ConfigError {
+    pub problems: Vec<(String, String)>,
+}
+
+impl ConfigError {
+    fn push(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.problems.push((path.into(), message.into()));
+    }
+
+    fn into_result(self) -> Result<(), Self> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problems.len() {
+            1 => write!(f, "config has a problem:")?,
+            count => write!(f, "config has {count} problems:")?,
+        }
+        for (path, message) in &self.problems {
+            write!(f, "\n  `{path}`: {}", message.replace('\n', "\n    "))?;
         }
         Ok(())
     }
 }
 
+impl std::error::Error for ConfigError {}
+
+/// Either just a path, or a table with `path` and `manifest`.
+///
+/// `*.anim`, `*.rhai`, `*.png` and `*.gif` files are registered. The manifest is a TOML
+/// file with a table per file stem, containing its `description` and any settings of the
+/// animation kind; it defaults to `manifest.toml` in the directory, if it exists.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "RawAnimationDir")]
+pub struct AnimationDir {
+    pub path: PathBuf,
+    pub manifest: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawAnimationDir {
+    Path(PathBuf),
+    Full {
+        path: PathBuf,
+        manifest: Option<PathBuf>,
+    },
+}
+
+impl From<RawAnimationDir> for AnimationDir {
+    fn from(raw: RawAnimationDir) -> Self {
+        match raw {
+            RawAnimationDir::Path(path) => Self {
+                path,
+                manifest: None,
+            },
+            RawAnimationDir::Full { path, manifest } => Self { path, manifest },
+        }
+    }
+}
+
+impl AnimationDir {
+    pub fn manifest_path(&self) -> PathBuf {
+        self.manifest
+            .clone()
+            .unwrap_or_else(|| self.path.join("manifest.toml"))
+    }
+}
+
+#[derive(Debug)]
+pub struct DiscoveredAnimation {
+    pub name: String,
+    pub path: PathBuf,
+    pub config: AnimationConfig,
+    pub description: Option<String>,
+}
+
+/// Either just a path to the serial device, or a table with `path` and `brightness`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(from = "RawDisplayConfig")]
+pub struct DisplayConfig {
+    pub path: String,
+    pub brightness: u8,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawDisplayConfig {
+    Path(String),
+    Full {
+        path: String,
+        #[serde(default = "default_display_brightness")]
+        brightness: u8,
+    },
+}
+
+fn default_display_brightness() -> u8 {
+    255
+}
+
+impl From<RawDisplayConfig> for DisplayConfig {
+    fn from(raw: RawDisplayConfig) -> Self {
+        match raw {
+            RawDisplayConfig::Path(path) => Self {
+                path,
+                brightness: default_display_brightness(),
+            },
+            RawDisplayConfig::Full { path, brightness } => Self { path, brightness },
+        }
+    }
+}
+
 #[derive(Default, Debug, Deserialize)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
 pub struct BuiltinConfig {
     pub charger: Option<ChargerConfig>,
+    /// Named progress bar styles; `default` is used when no style is requested.
+    pub progress: HashMap<String, ProgressStyle>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ChargerConfig {
     pub animation_left: String,
     pub animation_right: String,
@@ -70,20 +485,143 @@ fn default_right_display() -> String {
     "right".into()
 }
 
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProgressStyle {
+    pub direction: FillDirection,
+    pub start_brightness: u8,
+    pub end_brightness: u8,
+    #[serde(with = "humantime_serde::option")]
+    pub pulse_period: Option<Duration>,
+    #[serde(with = "humantime_serde")]
+    pub frame_duration: Duration,
+    /// How long to show the full bar after it's done.
+    #[serde(with = "humantime_serde")]
+    pub done_duration: Duration,
+    /// Bar is removed if it wasn't updated for this long.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+impl Default for ProgressStyle {
+    fn default() -> Self {
+        Self {
+            direction: FillDirection::Up,
+            start_brightness: 255,
+            end_brightness: 255,
+            pulse_period: None,
+            frame_duration: Duration::from_millis(50),
+            done_duration: Duration::from_millis(500),
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FillDirection {
+    #[default]
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(tag = "kind", rename_all = "lowercase")]
 pub enum AnimationConfig {
     Builtin(BuiltinAnimation),
     File(FileAnimation),
+    Script(ScriptAnimation),
+    Image(ImageAnimation),
+}
+
+impl AnimationConfig {
+    /// Checks settings and files which would otherwise only fail when building the
+    /// animation, or make it misbehave; `path` is where the animation is configured.
+    fn validate(&self, path: &str, errors: &mut ConfigError) {
+        // displays would redraw as fast as they can, never letting go of the CPU
+        if self
+            .frame_duration()
+            .is_some_and(|duration| duration.is_zero())
+        {
+            errors.push(format!("{path}.frame_duration"), "must not be zero");
+        }
+        match self {
+            Self::Builtin(BuiltinAnimation::Spread(spread)) => spread.validate(path, errors),
+            Self::Builtin(BuiltinAnimation::Automaton(automaton)) => {
+                if let AutomatonSeed::Random { density, .. } = automaton.seed
+                    && !(0. ..=1.).contains(&density)
+                {
+                    errors.push(format!("{path}.seed.density"), "must be between 0 and 1");
+                }
+                if let AutomatonSeed::File { .. } = automaton.seed
+                    && let Err(err) = Automaton::from_config(automaton)
+                {
+                    errors.push(format!("{path}.seed"), format!("{err:#}"));
+                }
+            }
+            Self::Builtin(BuiltinAnimation::Shader(_)) => {}
+            Self::File(file) => {
+                if let Err(err) = animations::file::FileAnimation::load(&file.path) {
+                    errors.push(format!("{path}.path"), format!("{err:#}"));
+                }
+            }
+            Self::Script(script) => {
+                if script.max_operations == 0 {
+                    errors.push(format!("{path}.max_operations"), "must not be zero");
+                }
+                if script.frame_budget.is_zero() {
+                    errors.push(format!("{path}.frame_budget"), "must not be zero");
+                }
+                if let Err(err) = animations::script::ScriptBuilder::new(script.clone()) {
+                    errors.push(format!("{path}.path"), format!("{err:#}"));
+                }
+            }
+            Self::Image(image) => {
+                if let Err(err) = animations::image::load(image) {
+                    errors.push(format!("{path}.paths"), format!("{err:#}"));
+                }
+            }
+        }
+    }
+
+    /// Duration of generated frames, for kinds which have one.
+    fn frame_duration(&self) -> Option<Duration> {
+        match self {
+            Self::Builtin(BuiltinAnimation::Spread(spread)) => Some(spread.frame_duration),
+            Self::Builtin(BuiltinAnimation::Automaton(automaton)) => Some(automaton.frame_duration),
+            Self::Builtin(BuiltinAnimation::Shader(shader)) => Some(shader.frame_duration),
+            Self::Script(script) => Some(script.frame_duration),
+            Self::Image(image) => Some(image.frame_duration),
+            // every frame has its own
+            Self::File(_) => None,
+        }
+    }
+
+    /// Human-readable kind, with the name for builtin animations.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Builtin(BuiltinAnimation::Spread(_)) => "builtin spread",
+            Self::Builtin(BuiltinAnimation::Automaton(_)) => "builtin automaton",
+            Self::Builtin(BuiltinAnimation::Shader(_)) => "builtin shader",
+            Self::File(_) => "file",
+            Self::Script(_) => "script",
+            Self::Image(_) => "image",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "name", rename_all = "lowercase")]
 pub enum BuiltinAnimation {
     Spread(SpreadAnimation),
+    Automaton(AutomatonAnimation),
+    Shader(ShaderAnimation),
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct SpreadAnimation {
     pub seeds: Vec<[u8; 3]>,
     #[serde(with = "humantime_serde")]
@@ -92,9 +630,337 @@ pub struct SpreadAnimation {
     pub horiz_cost: u8,
     pub vert_cost: u8,
     pub diag_cost: u8,
+    #[serde(default)]
+    pub decay: Decay,
+    #[serde(default)]
+    pub edges: Edges,
+    pub max_generations: Option<usize>,
+    /// Seeds that appear later in the animation rather than at the first frame.
+    #[serde(default)]
+    pub scheduled_seeds: Vec<ScheduledSeed>,
+    /// Brightness range (inclusive) for seeds requested with `random`.
+    #[serde(default = "default_random_brightness")]
+    pub random_brightness: [u8; 2],
+}
+
+impl SpreadAnimation {
+    fn validate(&self, path: &str, errors: &mut ConfigError) {
+        for (idx, &[x, y, _]) in self.seeds.iter().enumerate() {
+            if x > 8 || y > 33 {
+                errors.push(
+                    format!("{path}.seeds[{idx}]"),
+                    format!("({x}, {y}) is outside of the 9x34 matrix"),
+                );
+            }
+        }
+        for (idx, seed) in self.scheduled_seeds.iter().enumerate() {
+            if seed.x > 8 || seed.y > 33 {
+                errors.push(
+                    format!("{path}.scheduled_seeds[{idx}]"),
+                    format!("({}, {}) is outside of the 9x34 matrix", seed.x, seed.y),
+                );
+            }
+        }
+        let [min, max] = self.random_brightness;
+        if min > max {
+            errors.push(
+                format!("{path}.random_brightness"),
+                format!("minimum {min} is above maximum {max}"),
+            );
+        }
+
+        // without a generation limit, brightness has to drop with every step
+        if self.max_generations.is_some() {
+            return;
+        }
+        for (key, cost) in [
+            ("stay_cost", self.stay_cost),
+            ("horiz_cost", self.horiz_cost),
+            ("vert_cost", self.vert_cost),
+            ("diag_cost", self.diag_cost),
+        ] {
+            if cost == 0 {
+                errors.push(
+                    format!("{path}.{key}"),
+                    "is 0, so the animation never ends; raise it or set `max_generations`",
+                );
+            }
+        }
+        if let Decay::Exponential { growth } = self.decay
+            && (growth < 1. || growth.is_nan())
+        {
+            errors.push(
+                format!("{path}.decay.growth"),
+                "is below 1, so costs vanish and the animation never ends; raise it or set \
+                 `max_generations`",
+            );
+        }
+    }
+}
+
+fn default_random_brightness() -> [u8; 2] {
+    [255, 255]
+}
+
+/// How step costs are applied to brightness when spreading.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Decay {
+    /// Cost is subtracted from brightness.
+    #[default]
+    Linear,
+    /// Brightness is multiplied by `(256 - cost) / 256`.
+    Multiplicative,
+    /// Cost is multiplied by `growth` every generation before being subtracted.
+    Exponential { growth: f32 },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduledSeed {
+    pub frame: usize,
+    pub x: u8,
+    pub y: u8,
+    pub brightness: u8,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutomatonAnimation {
+    #[serde(default = "default_automaton_rule")]
+    pub rule: Rule,
+    #[serde(default)]
+    pub edges: Edges,
+    pub seed: AutomatonSeed,
+    #[serde(with = "humantime_serde")]
+    pub frame_duration: Duration,
+    #[serde(default = "default_automaton_brightness")]
+    pub brightness: u8,
+    pub max_generations: Option<usize>,
+}
+
+fn default_automaton_rule() -> Rule {
+    // Conway's Game of Life
+    "B3/S23".parse().unwrap()
+}
+
+fn default_automaton_brightness() -> u8 {
+    255
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Edges {
+    #[default]
+    Bounded,
+    Toroidal,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase", deny_unknown_fields)]
+pub enum AutomatonSeed {
+    /// Every lit pixel of the given frame is a live cell.
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        frame: usize,
+    },
+    Random {
+        seed: u64,
+        #[serde(default = "default_automaton_density")]
+        density: f32,
+    },
+}
+
+fn default_automaton_density() -> f32 {
+    0.3
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShaderAnimation {
+    pub expression: Expr,
+    #[serde(with = "humantime_serde")]
+    pub frame_duration: Duration,
+    /// Total length of the animation.
+    #[serde(with = "humantime_serde")]
+    pub duration: Duration,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct FileAnimation {
     pub path: PathBuf,
 }
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptAnimation {
+    pub path: PathBuf,
+    #[serde(with = "humantime_serde")]
+    pub frame_duration: Duration,
+    /// Rhai operations allowed per call, so a runaway script is stopped; must not be zero,
+    /// which Rhai takes as unlimited.
+    #[serde(default = "default_script_max_operations")]
+    pub max_operations: u64,
+    /// Wall-clock time allowed per call.
+    #[serde(default = "default_script_frame_budget", with = "humantime_serde")]
+    pub frame_budget: Duration,
+}
+
+fn default_script_max_operations() -> u64 {
+    1_000_000
+}
+
+fn default_script_frame_budget() -> Duration {
+    Duration::from_millis(10)
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageAnimation {
+    /// PNG files become single frames, GIF files contribute all of their frames.
+    pub paths: Vec<PathBuf>,
+    /// Used for PNG frames and GIF frames without a delay.
+    #[serde(default = "default_image_frame_duration", with = "humantime_serde")]
+    pub frame_duration: Duration,
+    /// Pixels at least this bright are lit; the animation is grayscale if not set.
+    pub threshold: Option<u8>,
+    /// Position of the top-left image corner on the display.
+    #[serde(default)]
+    pub x: u8,
+    #[serde(default)]
+    pub y: u8,
+    /// Cut images that don't fit instead of refusing to load them.
+    #[serde(default)]
+    pub crop: bool,
+    #[serde(default)]
+    pub fullscreen: bool,
+}
+
+fn default_image_frame_duration() -> Duration {
+    Duration::from_millis(100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spread_problems(extra: &str) -> Vec<(String, String)> {
+        let spread: SpreadAnimation = toml::from_str(&format!(
+            "seeds = [[4, 17, 255]]\nframe_duration = \"50ms\"\n\
+             stay_cost = 1\nhoriz_cost = 10\nvert_cost = 10\ndiag_cost = 14\n{extra}"
+        ))
+        .unwrap();
+        let mut errors = ConfigError::default();
+        spread.validate("animations.a", &mut errors);
+        errors.problems
+    }
+
+    #[test]
+    fn spread_validation() {
+        assert!(spread_problems("").is_empty());
+        assert_eq!(
+            spread_problems("random_brightness = [200, 100]"),
+            [(
+                "animations.a.random_brightness".to_owned(),
+                "minimum 200 is above maximum 100".to_owned()
+            )],
+        );
+        let paths = |extra| {
+            spread_problems(extra)
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths("scheduled_seeds = [{ x = 9, y = 0, brightness = 255, frame = 1 }]"),
+            ["animations.a.scheduled_seeds[0]"],
+        );
+        assert_eq!(
+            paths("decay = { kind = \"exponential\", growth = 0.5 }"),
+            ["animations.a.decay.growth"],
+        );
+        assert!(
+            paths("decay = { kind = \"exponential\", growth = 0.5 }\nmax_generations = 10")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn spread_costs_must_end_the_animation() {
+        let spread: SpreadAnimation = toml::from_str(
+            "seeds = [[9, 34, 255]]\nframe_duration = \"50ms\"\n\
+             stay_cost = 0\nhoriz_cost = 10\nvert_cost = 0\ndiag_cost = 14\n",
+        )
+        .unwrap();
+        let mut errors = ConfigError::default();
+        spread.validate("a", &mut errors);
+        let paths: Vec<_> = errors
+            .problems
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect();
+        assert_eq!(paths, ["a.seeds[0]", "a.stay_cost", "a.vert_cost"]);
+    }
+
+    fn animation_problems(config: &str) -> Vec<String> {
+        let animation: AnimationConfig = toml::from_str(config).unwrap();
+        let mut errors = ConfigError::default();
+        animation.validate("a", &mut errors);
+        errors.problems.into_iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    fn frame_duration_must_not_be_zero() {
+        let spread = "kind = \"builtin\"\nname = \"spread\"\nseeds = []\n\
+            stay_cost = 1\nhoriz_cost = 1\nvert_cost = 1\ndiag_cost = 1\n";
+        let automaton = "kind = \"builtin\"\nname = \"automaton\"\n\
+            seed = { kind = \"random\", seed = 1 }\n";
+        let shader = "kind = \"builtin\"\nname = \"shader\"\nexpression = \"x\"\n\
+            duration = \"1s\"\n";
+        let dir = tempfile::tempdir().unwrap();
+        let (script_path, image_path) = (dir.path().join("a.rhai"), dir.path().join("a.png"));
+        fs::write(&script_path, "fn frame(t, n) { canvas() }").unwrap();
+        ::image::GrayImage::new(1, 1).save(&image_path).unwrap();
+        let script = format!("kind = \"script\"\npath = {script_path:?}\n");
+        let image = format!("kind = \"image\"\npaths = [{image_path:?}]\n");
+        for config in [spread, automaton, shader, &script, &image] {
+            assert!(animation_problems(&format!("{config}frame_duration = \"50ms\"")).is_empty());
+            assert_eq!(
+                animation_problems(&format!("{config}frame_duration = \"0s\"")),
+                ["a.frame_duration"],
+                "{config}",
+            );
+        }
+        assert_eq!(
+            animation_problems(&format!(
+                "{script}frame_duration = \"50ms\"\nframe_budget = \"0s\""
+            )),
+            ["a.frame_budget"],
+        );
+    }
+
+    #[test]
+    fn animation_files_must_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let broken = dir.path().join("broken.rhai");
+        fs::write(&broken, "fn frame(t, n) {").unwrap();
+        let missing = dir.path().join("missing.rhai");
+        for path in [broken, missing] {
+            assert_eq!(
+                animation_problems(&format!(
+                    "kind = \"script\"\npath = {path:?}\nframe_duration = \"50ms\""
+                )),
+                ["a.path"],
+            );
+        }
+        let missing = dir.path().join("missing.png");
+        assert_eq!(
+            animation_problems(&format!(
+                "kind = \"image\"\npaths = [{missing:?}]\nframe_duration = \"50ms\""
+            )),
+            ["a.paths"],
+        );
+    }
+}
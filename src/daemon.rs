@@ -1,67 +1,347 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     convert::Infallible,
-    io::{BufRead as _, BufReader, Write as _},
-    os::unix::net::UnixListener,
-    str::FromStr as _,
+    fs,
+    io::{self, BufRead as _, BufReader, Read as _, Write as _},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
     sync::{
-        Arc,
+        Arc, Mutex, RwLock,
         atomic::{self, AtomicU64},
+        mpsc,
     },
     thread,
-    time::Instant,
+    time::{Duration, Instant, SystemTime},
 };
 
-use framework_lib::power::UsbPowerRoles;
-use tracing::{error, info, info_span};
+use eyre::WrapErr as _;
+use framework_lib::{chromium_ec::CrosEc, power::UsbPowerRoles};
+use notify::Watcher as _;
+use serde::Serialize;
+use serde_json::json;
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+use tracing::{error, info, info_span, warn};
 
 use crate::{
     MatrixPort,
-    animations::builder::AnimationBuilder,
-    config::Config,
-    display_thread::{self, DisplayCommand},
+    animations::{
+        Animation, FrameData,
+        builder::{AnimationBuilder, PlayArgs},
+        file, progress,
+        spread::Seeds,
+        stream,
+    },
+    config::{BuiltinConfig, Config, DisplayConfig},
+    display_thread::{self, DisplayCommand, DisplayEvent, DisplayEventKind, DisplayStatus},
 };
 
-pub fn run(config: Config) -> eyre::Result<Infallible> {
-    config.validate()?;
+pub mod protocol;
+
+use protocol::{
+    Ending, Error, ErrorCode, Event, MAX_RANDOM_SEEDS, PROTOCOL_VERSION, RandomSeeds, Reply,
+    ReplyLine, Request, RequestLine,
+};
 
-    let ec = Arc::new(framework_lib::chromium_ec::CrosEc::new());
+const SERVER: &str = concat!("fw-lights ", env!("CARGO_PKG_VERSION"));
 
-    let builtin_config = Arc::new(config.builtin);
+/// Everything built from the config, replaced as a whole on reload.
+struct State {
+    builtin: BuiltinConfig,
+    animations: HashMap<String, AnimationBuilder>,
+    animation_info: HashMap<String, AnimationInfo>,
+    displays: HashMap<String, Display>,
+    socket_path: PathBuf,
+    watch: bool,
+    /// Files read while building animations.
+    animation_files: Vec<PathBuf>,
+    /// Directories where new animation files may appear.
+    animation_dirs: Vec<PathBuf>,
+}
 
-    let animations = Arc::new(
-        config
+#[derive(Clone)]
+struct Display {
+    config: DisplayConfig,
+    sender: mpsc::Sender<DisplayCommand>,
+    status: Arc<Mutex<DisplayStatus>>,
+}
+
+#[derive(Serialize)]
+struct AnimationInfo {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+impl State {
+    /// Builds animations and opens displays, reusing running display threads of `old` by
+    /// device path so animations playing on them continue. Their brightness is left alone
+    /// until [`Self::update_brightness`].
+    fn new(
+        config: Config,
+        old: Option<&State>,
+        display_events: &mpsc::Sender<DisplayEvent>,
+    ) -> eyre::Result<Self> {
+        let animation_files = config.animation_files();
+        let animation_dirs = config
+            .animation_dirs
+            .iter()
+            .map(|dir| dir.path.clone())
+            .collect();
+        let mut descriptions = config.descriptions;
+        let animation_info = config
+            .animations
+            .iter()
+            .map(|(name, animation)| {
+                let info = AnimationInfo {
+                    kind: animation.kind(),
+                    description: descriptions.remove(name),
+                };
+                (name.clone(), info)
+            })
+            .collect();
+        let animations = config
             .animations
             .into_iter()
-            .map(|(name, config)| AnimationBuilder::new(config).map(|builder| (name, builder)))
-            .collect::<eyre::Result<HashMap<_, _>>>()?,
-    );
-    let displays = Arc::new(
-        config
-            .displays
-            .into_iter()
-            .map(|(name, path)| {
-                Ok((
-                    name,
-                    display_thread::Matrix::new(MatrixPort::open(&path)?)?.spawn(),
-                ))
+            .map(|(name, config)| {
+                AnimationBuilder::new(config)
+                    .wrap_err_with(|| format!("failed to load animation `{name}`"))
+                    .map(|builder| (name, builder))
             })
-            .collect::<eyre::Result<HashMap<_, _>>>()?,
-    );
+            .collect::<eyre::Result<HashMap<_, _>>>()?;
 
-    let reference = Instant::now();
+        let mut displays = HashMap::new();
+        for (name, display_config) in config.displays {
+            let reused = old.and_then(|old| {
+                old.displays.values().find(|display| {
+                    display.config.path == display_config.path
+                        // reopened instead, e.g. once the device is plugged back in
+                        && display.status.lock().unwrap().error.is_none()
+                })
+            });
+            let (sender, status) = match reused {
+                Some(display) => (display.sender.clone(), Arc::clone(&display.status)),
+                None => {
+                    let port = MatrixPort::open(&display_config.path).wrap_err_with(|| {
+                        format!(
+                            "failed to open display `{name}` at `{}`",
+                            display_config.path
+                        )
+                    })?;
+                    let matrix = display_thread::Matrix::new(
+                        port,
+                        display_config.path.clone(),
+                        display_events.clone(),
+                    )?;
+                    let status = matrix.status();
+                    let (sender, _thread) = matrix.spawn();
+                    sender.send(DisplayCommand::SetBrightness(display_config.brightness))?;
+                    (sender, status)
+                }
+            };
+            displays.insert(
+                name,
+                Display {
+                    config: display_config,
+                    sender,
+                    status,
+                },
+            );
+        }
+
+        Ok(Self {
+            builtin: config.builtin,
+            animations,
+            animation_info,
+            displays,
+            socket_path: config.socket_path,
+            watch: config.watch,
+            animation_files,
+            animation_dirs,
+        })
+    }
+
+    /// Applies brightness changes to display threads reused from `old`.
+    fn update_brightness(&self, old: &State) -> eyre::Result<()> {
+        for display in self.displays.values() {
+            let reused = old
+                .displays
+                .values()
+                .find(|old| Arc::ptr_eq(&old.status, &display.status));
+            if let Some(old) = reused
+                && old.config.brightness != display.config.brightness
+            {
+                display
+                    .sender
+                    .send(DisplayCommand::SetBrightness(display.config.brightness))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reloads whenever the config or any file referenced from it changes.
+fn watch(daemon: &Daemon) -> eyre::Result<Infallible> {
+    loop {
+        let mut files = vec![daemon.config_path.clone()];
+        let mut dirs = Vec::new();
+        {
+            let state = daemon.state.read().unwrap();
+            files.extend(state.animation_files.iter().cloned());
+            dirs.extend(state.animation_dirs.iter().cloned());
+        }
+
+        // editors often replace files instead of writing them, so directories are watched
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        let mut watched = HashSet::new();
+        for file in &files {
+            let (Some(dir), Some(name)) = (file.parent(), file.file_name()) else {
+                continue;
+            };
+            let dir = fs::canonicalize(if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            })?;
+            watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+            watched.insert(dir.join(name));
+        }
+        // any file appearing in or disappearing from animation directories matters
+        let mut watched_dirs = HashSet::new();
+        for dir in &dirs {
+            let dir = fs::canonicalize(dir)?;
+            watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+            watched_dirs.insert(dir);
+        }
+
+        loop {
+            let event = rx.recv()??;
+            if event.paths.iter().any(|path| {
+                watched.contains(path)
+                    || path.parent().is_some_and(|dir| watched_dirs.contains(dir))
+            }) {
+                break;
+            }
+        }
+        // let the burst of events from a single save settle
+        thread::sleep(Duration::from_millis(200));
+        while rx.try_recv().is_ok() {}
+
+        info!("config or animation files changed, reloading");
+        if let Err(err) = daemon.reload() {
+            error!("failed to reload: {err:#}");
+        }
+    }
+}
+
+/// Everything shared between connections.
+struct Daemon {
+    config_path: PathBuf,
+    state: RwLock<Arc<State>>,
+    /// Held while reloading, so `state` only needs locking to swap it.
+    reloading: Mutex<()>,
+    ec: CrosEc,
+    reference: Instant,
     // TODO: this should really be replaced with some sort of "already-playing" detection
-    let charger_last_played = Arc::new(AtomicU64::new(reference.elapsed().as_millis() as u64));
+    charger_last_played: AtomicU64,
+    progress_bars: Mutex<HashMap<String, progress::ProgressHandle>>,
+    /// Passed to display threads opened on reload.
+    display_events: mpsc::Sender<DisplayEvent>,
+    subscribers: Mutex<Vec<mpsc::Sender<Event>>>,
+    next_animation_id: AtomicU64,
+    /// By animation ID.
+    waiters: Mutex<HashMap<u64, Waiter>>,
+    /// Where `define`d animations are persisted, fixed at startup.
+    state_dir: Option<PathBuf>,
+    /// Animations from `define`, which survive reloads.
+    defined: RwLock<HashMap<String, AnimationBuilder>>,
+}
+
+/// A `play ... wait` request waiting for its animation to be over.
+struct Waiter {
+    device: String,
+    /// When the display thread picked up the animation.
+    started: Option<Instant>,
+    sender: mpsc::Sender<(Ending, Duration)>,
+}
+
+struct Connection {
+    /// Shared with the thread writing events.
+    writer: Arc<Mutex<UnixStream>>,
+    /// Negotiated by `hello`, JSON requests are refused until then.
+    version: Option<u32>,
+    subscribed: bool,
+    /// Set by `stream`, after which the connection only carries frames.
+    streaming: Option<Streaming>,
+}
+
+struct Streaming {
+    frames: mpsc::Sender<FrameData>,
+    binary: bool,
+}
+
+pub fn run(config_path: &Path) -> eyre::Result<Infallible> {
+    let config = Config::load(config_path)?;
+    config.validate_displays()?;
+    let state_dir = config.state_dir.clone();
+    let defined = match &state_dir {
+        Some(state_dir) => load_defined(state_dir)?,
+        None => HashMap::new(),
+    };
+    let (display_events, display_event_rx) = mpsc::channel();
+    let state = State::new(config, None, &display_events)?;
+    let socket = UnixListener::bind(&state.socket_path)?;
+    let watch_files = state.watch;
 
-    let socket = UnixListener::bind(&config.socket_path)?;
+    let reference = Instant::now();
+    let daemon = Arc::new(Daemon {
+        config_path: config_path.to_owned(),
+        state: RwLock::new(Arc::new(state)),
+        reloading: Mutex::new(()),
+        ec: CrosEc::new(),
+        reference,
+        charger_last_played: AtomicU64::new(reference.elapsed().as_millis() as u64),
+        progress_bars: Mutex::new(HashMap::new()),
+        display_events,
+        subscribers: Mutex::new(Vec::new()),
+        next_animation_id: AtomicU64::new(1),
+        waiters: Mutex::new(HashMap::new()),
+        state_dir,
+        defined: RwLock::new(defined),
+    });
+
+    {
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            for event in display_event_rx {
+                daemon.forward(event);
+            }
+        });
+    }
+
+    let mut signals = Signals::new([SIGHUP])?;
+    {
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                info!("got SIGHUP, reloading");
+                if let Err(err) = daemon.reload() {
+                    error!("failed to reload: {err:#}");
+                }
+            }
+        });
+    }
+    if watch_files {
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            let Err(err) = watch(&daemon);
+            error!("stopped watching files: {err:#}");
+        });
+    }
 
     loop {
         let (stream, _addr) = socket.accept()?;
-        let ec = Arc::clone(&ec);
-        let builtin_config = Arc::clone(&builtin_config);
-        let animations = Arc::clone(&animations);
-        let displays = Arc::clone(&displays);
-        let charger_last_played = Arc::clone(&charger_last_played);
+        let daemon = Arc::clone(&daemon);
         thread::spawn(move || -> eyre::Result<()> {
             let span = info_span!(
                 "worker thread",
@@ -69,6 +349,12 @@ pub fn run(config: Config) -> eyre::Result<Infallible> {
             );
             let _guard = span.enter();
 
+            let mut connection = Connection {
+                writer: Arc::new(Mutex::new(stream.try_clone()?)),
+                version: None,
+                subscribed: false,
+                streaming: None,
+            };
             let mut stream = BufReader::new(stream);
             let mut line = String::new();
             loop {
@@ -76,95 +362,948 @@ pub fn run(config: Config) -> eyre::Result<Infallible> {
                 if stream.read_line(&mut line)? == 0 {
                     return Ok(());
                 }
-                // not easy to avoid this allocation due to borrow checker
-                let words: Vec<_> = line.split_ascii_whitespace().collect();
-                match words.as_slice() {
-                    ["charger"] => {
-                        info!("asked to play charger animation");
-                        let now = reference.elapsed().as_millis() as u64;
-                        let last_played = charger_last_played.fetch_update(
-                            atomic::Ordering::Relaxed,
-                            atomic::Ordering::Relaxed,
-                            |old| {
-                                if now - old > 1000 { Some(now) } else { None }
-                            },
-                        );
-                        if let Err(old) = last_played {
-                            info!(old = old, now = now, "throttled charger animation");
-
-                            stream.get_mut().write_all(b"OK throttled\n")?;
-                            continue;
-                        }
-
-                        let Some(config) = &builtin_config.charger else {
-                            error!("no config for charger animation");
-                            stream.get_mut().write_all(b"ERR no config")?;
-                            continue;
-                        };
 
-                        for (idx, port) in framework_lib::power::get_pd_info(&ec, 4)
-                            .into_iter()
-                            .enumerate()
+                let reply = if line.trim_start().starts_with('{') {
+                    let reply = daemon.handle_json(&line, &mut connection);
+                    let mut reply = serde_json::to_string(&reply)?;
+                    reply.push('\n');
+                    reply
+                } else {
+                    let definition =
+                        protocol::has_body(&line).then(|| read_definition(&mut stream));
+                    let mut result = protocol::parse_text(&line).and_then(|mut request| {
+                        if let Request::Define { body, .. } = &mut request
+                            && let Some(definition) = definition
                         {
-                            if let Ok(port) = port {
-                                if matches!(port.role, UsbPowerRoles::Sink) {
-                                    let (side, animation, offset) = match idx {
-                                        0 => (&config.right_display, &config.animation_right, 14),
-                                        1 => (&config.right_display, &config.animation_right, 24),
-                                        2 => (&config.left_display, &config.animation_left, 24),
-                                        3 => (&config.left_display, &config.animation_left, 14),
-                                        // unknown port
-                                        _ => continue,
-                                    };
-                                    // already validated
-                                    info!(%side, %animation, %offset, "playing charger animation");
-                                    let display = &displays[side].0;
-                                    let animation =
-                                        animations[animation].at(offset + config.offset);
-                                    display.send(DisplayCommand::AddAnimation(animation))?;
-                                }
+                            *body = definition.map_err(|err| {
+                                Error::new(ErrorCode::BadRequest, err.to_string())
+                            })?;
+                        }
+                        daemon.execute(request, &mut connection)
+                    });
+                    // animation IDs are only useful alongside JSON events
+                    if let Ok(serde_json::Value::Object(result)) = &mut result {
+                        result.remove("id");
+                    }
+                    match result {
+                        Ok(result) => match result.get("throttled") {
+                            Some(serde_json::Value::Bool(true)) => "OK throttled\n".to_owned(),
+                            Some(_) => "OK\n".to_owned(),
+                            None if result == json!({}) => "OK\n".to_owned(),
+                            // query results
+                            None => format!("OK {result}\n"),
+                        },
+                        Err(err) => {
+                            if err.code == ErrorCode::UnknownCommand {
+                                error!(command = line, "got unknown command");
                             }
+                            format!("ERR {err}\n")
                         }
+                    }
+                };
+                connection
+                    .writer
+                    .lock()
+                    .unwrap()
+                    .write_all(reply.as_bytes())?;
+
+                if let Some(streaming) = connection.streaming.take() {
+                    receive_frames(&mut stream, &connection, &streaming)?;
+                    info!("stream ended");
+                    return Ok(());
+                }
+            }
+        });
+    }
+}
 
-                        stream.get_mut().write_all(b"OK\n")?;
+impl Daemon {
+    fn handle_json(&self, line: &str, connection: &mut Connection) -> ReplyLine {
+        let RequestLine { id, request } = match RequestLine::parse(line) {
+            Ok(line) => line,
+            Err(reply) => return reply,
+        };
+
+        let result = match (request, connection.version) {
+            (Request::Hello { version: requested }, _) => {
+                if requested == 0 {
+                    Err(Error::new(
+                        ErrorCode::UnsupportedVersion,
+                        format!("protocol versions start at 1, up to {PROTOCOL_VERSION}"),
+                    ))
+                } else {
+                    let negotiated = requested.min(PROTOCOL_VERSION);
+                    connection.version = Some(negotiated);
+                    Ok(json!({
+                        "version": negotiated,
+                        "server": SERVER,
+                    }))
+                }
+            }
+            (_, None) => Err(Error::new(
+                ErrorCode::HandshakeRequired,
+                "send `hello` before other JSON requests",
+            )),
+            (request, Some(_)) => self.execute(request, connection),
+        };
+        ReplyLine {
+            id,
+            reply: match result {
+                Ok(result) => Reply::Ok(result),
+                Err(err) => Reply::Error(err),
+            },
+        }
+    }
+
+    /// Runs a command, returning its result for JSON replies.
+    fn execute(
+        &self,
+        request: Request,
+        connection: &mut Connection,
+    ) -> Result<serde_json::Value, Error> {
+        // commands see a consistent state even if a reload happens meanwhile
+        let state = Arc::clone(&self.state.read().unwrap());
+        match request {
+            Request::Hello { .. } => Err(Error::new(
+                ErrorCode::BadRequest,
+                "`hello` is only supported in JSON",
+            )),
+            Request::Reload => {
+                info!("asked to reload");
+                self.reload().map_err(|err| {
+                    error!("failed to reload: {err:#}");
+                    Error::new(ErrorCode::ReloadFailed, one_line(&err))
+                })?;
+                Ok(json!({}))
+            }
+            Request::Charger => {
+                info!("asked to play charger animation");
+                let Some(config) = &state.builtin.charger else {
+                    error!("no config for charger animation");
+                    return Err(Error::new(ErrorCode::NoConfig, "no config"));
+                };
+
+                let now = self.reference.elapsed().as_millis() as u64;
+                let last_played = self.charger_last_played.fetch_update(
+                    atomic::Ordering::Relaxed,
+                    atomic::Ordering::Relaxed,
+                    |old| {
+                        if now - old > 1000 { Some(now) } else { None }
+                    },
+                );
+                if let Err(old) = last_played {
+                    info!(old = old, now = now, "throttled charger animation");
+                    self.emit(Event::Charger { throttled: true });
+                    return Ok(json!({ "throttled": true }));
+                }
+                self.emit(Event::Charger { throttled: false });
+
+                for (idx, port) in framework_lib::power::get_pd_info(&self.ec, 4)
+                    .into_iter()
+                    .enumerate()
+                {
+                    #[allow(clippy::collapsible_if)]
+                    if let Ok(port) = port {
+                        if matches!(port.role, UsbPowerRoles::Sink) {
+                            let (side, animation, offset) = match idx {
+                                0 => (&config.right_display, &config.animation_right, 14),
+                                1 => (&config.right_display, &config.animation_right, 24),
+                                2 => (&config.left_display, &config.animation_left, 24),
+                                3 => (&config.left_display, &config.animation_left, 14),
+                                // unknown port
+                                _ => continue,
+                            };
+                            // already validated
+                            info!(%side, %animation, %offset, "playing charger animation");
+                            let builder = &state.animations[animation];
+                            self.play(
+                                &state.displays[side],
+                                animation,
+                                builder.at(offset + config.offset),
+                            )?;
+                        }
                     }
-                    &["play", animation, "at", display, ref args @ ..] => {
-                        let display_name = display;
-                        info!(%animation, %display_name, "asked to play animation");
-
-                        let Some((display, _thread)) = displays.get(display) else {
-                            error!(%display_name, "bad display");
-                            stream.get_mut().write_all(b"ERR bad display\n")?;
-                            continue;
-                        };
-                        let Some(animation_builder) = animations.get(animation) else {
-                            error!(%animation, "bad animation");
-                            stream.get_mut().write_all(b"ERR bad animation\n")?;
-                            continue;
-                        };
-                        let animation = match args {
-                            [] => animation_builder.build(),
-                            ["offset", offset] => {
-                                let Ok(offset) = i8::from_str(offset) else {
-                                    stream.get_mut().write_all(b"ERR bad offset\n")?;
-                                    continue;
-                                };
-                                animation_builder.at(offset)
-                            }
-                            _ => {
-                                stream.get_mut().write_all(b"ERR bad args\n")?;
-                                continue;
-                            }
+                }
+                Ok(json!({ "throttled": false }))
+            }
+            Request::Play {
+                animation,
+                display,
+                offset,
+                seeds,
+                random,
+                wait,
+            } => {
+                let display_name = display;
+                info!(%animation, %display_name, "asked to play animation");
+
+                let display = state.display(&display_name)?;
+                let defined = self.defined.read().unwrap();
+                let Some(animation_builder) = state
+                    .animations
+                    .get(&animation)
+                    .or_else(|| defined.get(&animation))
+                else {
+                    error!(%animation, "bad animation");
+                    return Err(Error::new(ErrorCode::BadAnimation, "bad animation"));
+                };
+                let seeds = match (seeds, random) {
+                    (Some(_), Some(_)) => {
+                        return Err(Error::new(ErrorCode::BadArgs, "bad args"));
+                    }
+                    (Some(seeds), None) => {
+                        if seeds.iter().any(|&[x, y, _]| x > 8 || y > 33) {
+                            return Err(Error::new(ErrorCode::BadArgs, "bad seed"));
+                        }
+                        Some(Seeds::Explicit(seeds))
+                    }
+                    // JSON requests skip the text parser's check
+                    (None, Some(RandomSeeds { count, .. })) if count > MAX_RANDOM_SEEDS => {
+                        return Err(Error::new(ErrorCode::BadArgs, "bad seed count"));
+                    }
+                    (None, Some(RandomSeeds { count, rng_seed })) => Some(Seeds::Random {
+                        count,
+                        rng_seed: rng_seed.unwrap_or_else(time_seed),
+                    }),
+                    (None, None) => None,
+                };
+                if seeds.is_some() && !animation_builder.is_seedable() {
+                    return Err(Error::new(ErrorCode::NotSeedable, "animation has no seeds"));
+                }
+                let animation_name = animation;
+                let animation = animation_builder.with(&PlayArgs { offset, seeds });
+                // `define` shouldn't have to wait for this
+                drop(defined);
+                if wait {
+                    return self.play_and_wait(state, &display_name, &animation_name, animation);
+                }
+                let id = self.play(display, &animation_name, animation)?;
+                Ok(json!({ "id": id }))
+            }
+            Request::ProgressStart { id, display, style } => {
+                let display_name = display;
+                info!(%id, %display_name, "asked to start progress bar");
+                // they couldn't be updated with the text syntax
+                if id == "start" || id == "done" {
+                    return Err(Error::new(ErrorCode::BadArgs, "reserved id"));
+                }
+
+                let display = state.display(&display_name)?;
+                let style = match style {
+                    None => state
+                        .builtin
+                        .progress
+                        .get("default")
+                        .cloned()
+                        .unwrap_or_default(),
+                    Some(style) => {
+                        let Some(style) = state.builtin.progress.get(&style) else {
+                            error!(%style, "bad progress style");
+                            return Err(Error::new(ErrorCode::BadStyle, "bad style"));
                         };
-                        display.send(DisplayCommand::AddAnimation(animation))?;
-                        stream.get_mut().write_all(b"OK\n")?;
+                        style.clone()
                     }
-                    _ => {
-                        error!(command = line, "got unknown command");
-                        stream.get_mut().write_all(b"ERR unknown command\n")?;
+                };
+
+                let mut progress_bars = self.progress_bars.lock().unwrap();
+                progress_bars.retain(|_, bar| !bar.is_finished());
+                if progress_bars.contains_key(&id) {
+                    error!(%id, "progress bar already exists");
+                    return Err(Error::new(
+                        ErrorCode::ProgressBarExists,
+                        "progress bar exists",
+                    ));
+                }
+                let (handle, animation) = progress::start(style);
+                self.play(display, &format!("progress:{id}"), animation)?;
+                progress_bars.insert(id, handle);
+                Ok(json!({}))
+            }
+            Request::ProgressSet { id, value } => {
+                self.with_progress_bar(&id, |bar| bar.set(value))?;
+                Ok(json!({}))
+            }
+            Request::ProgressDone { id } => {
+                info!(%id, "progress bar done");
+                self.with_progress_bar(&id, |bar| bar.finish())?;
+                self.progress_bars.lock().unwrap().remove(&id);
+                Ok(json!({}))
+            }
+            Request::Stop {
+                display: display_name,
+            } => {
+                info!(%display_name, "asked to stop animations");
+                state.display(&display_name)?.send(DisplayCommand::Clear)?;
+                Ok(json!({}))
+            }
+            Request::Brightness {
+                display: display_name,
+                value,
+            } => {
+                info!(%display_name, %value, "asked to set brightness");
+                state
+                    .display(&display_name)?
+                    .send(DisplayCommand::SetBrightness(value))?;
+                Ok(json!({}))
+            }
+            Request::ListDisplays => {
+                let mut displays: Vec<_> = state
+                    .displays
+                    .iter()
+                    .map(|(name, display)| {
+                        json!({
+                            "name": name,
+                            "path": display.config.path,
+                            // changed by `brightness`, unlike the config
+                            "brightness": display.status.lock().unwrap().brightness,
+                        })
+                    })
+                    .collect();
+                displays.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+                Ok(json!({ "displays": displays }))
+            }
+            Request::ListAnimations => {
+                let mut animations: Vec<_> = state
+                    .animation_info
+                    .iter()
+                    .map(|(name, info)| {
+                        let mut animation = json!(info);
+                        animation["name"] = json!(name);
+                        animation
+                    })
+                    .collect();
+                animations.extend(
+                    self.defined
+                        .read()
+                        .unwrap()
+                        .keys()
+                        .filter(|name| !state.animations.contains_key(*name))
+                        .map(|name| json!({ "name": name, "kind": "defined" })),
+                );
+                animations.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+                Ok(json!({ "animations": animations }))
+            }
+            Request::Status { display } => {
+                let displays = match display {
+                    Some(name) => vec![(name.clone(), state.display(&name)?)],
+                    None => state
+                        .displays
+                        .iter()
+                        .map(|(name, display)| (name.clone(), display))
+                        .collect(),
+                };
+                let displays: serde_json::Map<_, _> = displays
+                    .into_iter()
+                    .map(|(name, display)| {
+                        let status = display.status.lock().unwrap().clone();
+                        (name, json!(status))
+                    })
+                    .collect();
+                Ok(json!({ "displays": displays }))
+            }
+            Request::Version => Ok(json!({
+                "server": SERVER,
+                "protocol": PROTOCOL_VERSION,
+            })),
+            Request::Subscribe => {
+                if !connection.subscribed {
+                    connection.subscribed = true;
+                    let (sender, receiver) = mpsc::channel();
+                    self.subscribers.lock().unwrap().push(sender);
+                    let writer = Arc::clone(&connection.writer);
+                    let json = connection.version.is_some();
+                    thread::spawn(move || {
+                        for event in receiver {
+                            // can't fail, every field is a string, number or bool
+                            let event = serde_json::to_string(&event).unwrap();
+                            let line = if json {
+                                format!("{event}\n")
+                            } else {
+                                format!("EVENT {event}\n")
+                            };
+                            // the connection was closed, dropping `receiver` unsubscribes
+                            if writer.lock().unwrap().write_all(line.as_bytes()).is_err() {
+                                return;
+                            }
+                        }
+                    });
+                }
+                Ok(json!({}))
+            }
+            Request::Stream {
+                display: display_name,
+                binary,
+                timeout,
+            } => {
+                info!(%display_name, %binary, "asked to stream frames");
+                let display = state.display(&display_name)?;
+                let (frames, animation) = stream::start(timeout.unwrap_or(stream::DEFAULT_TIMEOUT));
+                let id = self.play(display, "stream", animation)?;
+                connection.streaming = Some(Streaming { frames, binary });
+                Ok(json!({ "id": id }))
+            }
+            Request::Define { name, body } => {
+                info!(%name, "asked to define animation");
+                if state.animations.contains_key(&name) {
+                    return Err(Error::new(
+                        ErrorCode::AnimationConfigured,
+                        "animation is configured",
+                    ));
+                }
+                // also keeps it a single word and a plain file name
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+                {
+                    return Err(Error::new(ErrorCode::BadArgs, "bad name"));
+                }
+                // parsing from a string refuses `include`, which would read files
+                // relative to wherever the daemon runs
+                let animation = body.parse::<file::FileAnimation>().map_err(|err| {
+                    let message = match err.downcast_ref::<file::ParseError>() {
+                        Some(err) => summarize(err),
+                        None => one_line(&err),
+                    };
+                    Error::new(ErrorCode::BadDefinition, message)
+                })?;
+
+                let mut defined = self.defined.write().unwrap();
+                if let Some(state_dir) = &self.state_dir {
+                    let dir = defined_dir(state_dir);
+                    fs::create_dir_all(&dir)
+                        .and_then(|()| fs::write(dir.join(format!("{name}.anim")), &body))
+                        .map_err(|err| {
+                            error!(%name, "failed to persist animation: {err}");
+                            Error::new(
+                                ErrorCode::Internal,
+                                format!("failed to persist animation: {err}"),
+                            )
+                        })?;
+                }
+                defined.insert(name, AnimationBuilder::file(animation));
+                Ok(json!({}))
+            }
+            Request::Undefine { name } => {
+                info!(%name, "asked to undefine animation");
+                let mut defined = self.defined.write().unwrap();
+                if !defined.contains_key(&name) {
+                    return Err(if state.animations.contains_key(&name) {
+                        Error::new(ErrorCode::AnimationConfigured, "animation is configured")
+                    } else {
+                        Error::new(ErrorCode::BadAnimation, "bad animation")
+                    });
+                }
+                if let Some(state_dir) = &self.state_dir {
+                    match fs::remove_file(defined_dir(state_dir).join(format!("{name}.anim"))) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => {
+                            error!(%name, "failed to remove persisted animation: {err}");
+                            return Err(Error::new(
+                                ErrorCode::Internal,
+                                format!("failed to remove persisted animation: {err}"),
+                            ));
+                        }
                     }
                 }
+                defined.remove(&name);
+                Ok(json!({}))
+            }
+        }
+    }
+
+    /// Plays an animation under a fresh ID, which is returned.
+    fn play(&self, display: &Display, name: &str, animation: Animation) -> Result<u64, Error> {
+        self.start(display, name, animation, None)
+    }
+
+    /// Plays an animation and blocks until it's over.
+    fn play_and_wait(
+        &self,
+        state: Arc<State>,
+        display_name: &str,
+        name: &str,
+        animation: Animation,
+    ) -> Result<serde_json::Value, Error> {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.start(state.display(display_name)?, name, animation, Some(sender))?;
+        // display threads stop once a reload removed them and their state is dropped
+        drop(state);
+        let (ending, duration) = receiver
+            .recv()
+            .map_err(|_| Error::new(ErrorCode::Internal, "lost track of the animation"))?;
+        Ok(json!({
+            "id": id,
+            "ending": ending,
+            "duration_ms": duration.as_millis() as u64,
+        }))
+    }
+
+    fn start(
+        &self,
+        display: &Display,
+        name: &str,
+        animation: Animation,
+        waiter: Option<mpsc::Sender<(Ending, Duration)>>,
+    ) -> Result<u64, Error> {
+        let id = self
+            .next_animation_id
+            .fetch_add(1, atomic::Ordering::Relaxed);
+        // registered before sending, so the animation can't be over before
+        if let Some(sender) = waiter {
+            let waiter = Waiter {
+                device: display.config.path.clone(),
+                started: None,
+                sender,
+            };
+            self.waiters.lock().unwrap().insert(id, waiter);
+        }
+        let command = DisplayCommand::AddAnimation {
+            id,
+            name: name.to_owned(),
+            animation,
+        };
+        if let Err(err) = display.send(command) {
+            self.waiters.lock().unwrap().remove(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    fn emit(&self, event: Event) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Turns an event of a display thread into one for subscribers.
+    fn forward(&self, event: DisplayEvent) {
+        self.wake_waiters(&event);
+        let display_name = {
+            let state = self.state.read().unwrap();
+            state
+                .displays
+                .iter()
+                .find(|(_, display)| display.config.path == event.device)
+                .map(|(name, _)| name.clone())
+        }
+        // removed by a reload meanwhile
+        .unwrap_or(event.device);
+        self.emit(match event.kind {
+            DisplayEventKind::AnimationStarted(animation) => Event::AnimationStarted {
+                id: animation.id,
+                display: display_name,
+                animation: animation.name,
+            },
+            DisplayEventKind::AnimationFinished { animation, stopped } => {
+                Event::AnimationFinished {
+                    id: animation.id,
+                    display: display_name,
+                    animation: animation.name,
+                    stopped,
+                }
+            }
+            DisplayEventKind::Died { error } => {
+                error!(%display_name, "display thread died: {error}");
+                Event::DisplayOffline {
+                    display: display_name,
+                    reason: error,
+                }
             }
         });
     }
+
+    fn wake_waiters(&self, event: &DisplayEvent) {
+        let mut waiters = self.waiters.lock().unwrap();
+        match &event.kind {
+            DisplayEventKind::AnimationStarted(animation) => {
+                if let Some(waiter) = waiters.get_mut(&animation.id) {
+                    waiter.started = Some(Instant::now());
+                }
+            }
+            DisplayEventKind::AnimationFinished { animation, stopped } => {
+                if let Some(waiter) = waiters.remove(&animation.id) {
+                    let ending = if *stopped {
+                        Ending::Stopped
+                    } else {
+                        Ending::Finished
+                    };
+                    // the connection may be gone
+                    let _ = waiter.sender.send((ending, waiter.ran_for()));
+                }
+            }
+            DisplayEventKind::Died { .. } => waiters.retain(|_, waiter| {
+                if waiter.device != event.device {
+                    return true;
+                }
+                let _ = waiter.sender.send((Ending::DisplayDied, waiter.ran_for()));
+                false
+            }),
+        }
+    }
+
+    /// Replaces the state with one built from a freshly read config, keeping the old state
+    /// if anything is wrong with the new one.
+    fn reload(&self) -> eyre::Result<()> {
+        let result = self.replace_state();
+        self.emit(match &result {
+            Ok(()) => Event::Reloaded,
+            Err(err) => Event::ReloadFailed {
+                message: one_line(err),
+            },
+        });
+        result
+    }
+
+    fn replace_state(&self) -> eyre::Result<()> {
+        let _reloading = self.reloading.lock().unwrap();
+        let config = Config::load(&self.config_path)?;
+        config.validate_displays()?;
+        let state = Arc::clone(&self.state.read().unwrap());
+        if config.socket_path != state.socket_path {
+            warn!("changing `socket_path` requires a restart");
+        }
+        if config.watch != state.watch {
+            warn!("changing `watch` requires a restart");
+        }
+        if config.state_dir != self.state_dir {
+            warn!("changing `state_dir` requires a restart");
+        }
+        let new_state = State::new(config, Some(&state), &self.display_events)?;
+        for name in self.defined.read().unwrap().keys() {
+            if new_state.animations.contains_key(name) {
+                warn!(%name, "configured animation hides the defined one");
+            }
+        }
+
+        new_state.update_brightness(&state)?;
+
+        let has_device = |state: &State, path: &str| {
+            state
+                .displays
+                .values()
+                .any(|display| display.config.path == path)
+        };
+        for (name, display) in &new_state.displays {
+            if !has_device(&state, &display.config.path) {
+                self.emit(Event::DisplayOnline {
+                    display: name.clone(),
+                });
+            }
+        }
+        for (name, display) in &state.displays {
+            if !has_device(&new_state, &display.config.path) {
+                self.emit(Event::DisplayOffline {
+                    display: name.clone(),
+                    reason: "removed from config".to_owned(),
+                });
+            }
+        }
+
+        *self.state.write().unwrap() = Arc::new(new_state);
+        info!("reloaded config");
+        Ok(())
+    }
+
+    fn with_progress_bar(
+        &self,
+        id: &str,
+        f: impl FnOnce(&progress::ProgressHandle),
+    ) -> Result<(), Error> {
+        let mut progress_bars = self.progress_bars.lock().unwrap();
+        progress_bars.retain(|_, bar| !bar.is_finished());
+        let Some(bar) = progress_bars.get(id) else {
+            error!(%id, "bad progress bar");
+            return Err(Error::new(ErrorCode::BadProgressBar, "bad progress bar"));
+        };
+        f(bar);
+        Ok(())
+    }
+}
+
+impl State {
+    fn display(&self, name: &str) -> Result<&Display, Error> {
+        self.displays.get(name).ok_or_else(|| {
+            error!(display_name = name, "bad display");
+            Error::new(ErrorCode::BadDisplay, "bad display")
+        })
+    }
+}
+
+impl Waiter {
+    fn ran_for(&self) -> Duration {
+        self.started
+            .map(|started| started.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+impl Display {
+    fn send(&self, command: DisplayCommand) -> Result<(), Error> {
+        self.sender
+            .send(command)
+            .map_err(|_| Error::new(ErrorCode::Internal, "display thread has stopped"))
+    }
+}
+
+/// Passes frames from a streaming connection to its layer until either of them is gone.
+fn receive_frames(
+    stream: &mut BufReader<UnixStream>,
+    connection: &Connection,
+    streaming: &Streaming,
+) -> eyre::Result<()> {
+    // there are no requests to reply to anymore, but errors are still worth reporting
+    let report = |err: Error| -> io::Result<()> {
+        let line = if connection.version.is_some() {
+            let reply = ReplyLine {
+                id: None,
+                reply: Reply::Error(err),
+            };
+            format!("{}\n", json!(reply))
+        } else {
+            format!("ERR {err}\n")
+        };
+        connection.writer.lock().unwrap().write_all(line.as_bytes())
+    };
+
+    loop {
+        let frame = if streaming.binary {
+            let mut bytes = [0; stream::BINARY_FRAME_SIZE];
+            match stream.read_exact(&mut bytes) {
+                Ok(()) => stream::from_binary(&bytes),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        } else {
+            let mut rows = String::new();
+            let mut row_count = 0;
+            while row_count < 34 {
+                let mut line = String::new();
+                if stream.read_line(&mut line)? == 0 {
+                    return Ok(());
+                }
+                // between frames
+                if line.trim().is_empty() {
+                    continue;
+                }
+                // dropped without counting it as a row, so the following frames still line up
+                if line.trim_start().starts_with('{') {
+                    report(Error::new(
+                        ErrorCode::BadFrame,
+                        "frame options can't be streamed",
+                    ))?;
+                    continue;
+                }
+                rows.push_str(&line);
+                row_count += 1;
+            }
+            match file::parse_frame(&rows) {
+                Ok(frame) => frame.data,
+                Err(err) => {
+                    report(Error::new(ErrorCode::BadFrame, summarize(&err)))?;
+                    continue;
+                }
+            }
+        };
+        if streaming.frames.send(frame).is_err() {
+            report(Error::new(ErrorCode::StreamEnded, "stream ended"))?;
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the body of a text `define` up to its `end` line.
+fn read_definition(stream: &mut BufReader<UnixStream>) -> io::Result<String> {
+    let (mut body, mut line) = (String::new(), String::new());
+    loop {
+        line.clear();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if line.trim_end() == "end" {
+            return Ok(body);
+        }
+        body.push_str(&line);
+    }
+}
+
+/// Directory under `state_dir` holding `define`d animations as `<name>.anim`.
+fn defined_dir(state_dir: &Path) -> PathBuf {
+    state_dir.join("animations")
+}
+
+/// Loads animations persisted by `define`; broken ones are skipped, as they shouldn't keep
+/// the daemon from starting.
+fn load_defined(state_dir: &Path) -> eyre::Result<HashMap<String, AnimationBuilder>> {
+    let dir = defined_dir(state_dir);
+    let mut defined = HashMap::new();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(defined),
+        Err(err) => {
+            return Err(err).wrap_err_with(|| format!("failed to read `{}`", dir.display()));
+        }
+    };
+    for entry in entries {
+        let path = entry?.path();
+        let (Some(name), Some("anim")) = (
+            path.file_stem().and_then(|stem| stem.to_str()),
+            path.extension().and_then(|extension| extension.to_str()),
+        ) else {
+            continue;
+        };
+        let animation = fs::read_to_string(&path)
+            .map_err(eyre::Report::from)
+            .and_then(|body| body.parse::<file::FileAnimation>());
+        match animation {
+            Ok(animation) => {
+                defined.insert(name.to_owned(), AnimationBuilder::file(animation));
+            }
+            Err(err) => warn!(path = %path.display(), "skipping broken defined animation: {err:#}"),
+        }
+    }
+    info!(count = defined.len(), "loaded defined animations");
+    Ok(defined)
+}
+
+/// First problem of a parse error on a single line, as its snippets don't fit in replies.
+fn summarize(err: &file::ParseError) -> String {
+    let first = &err.diagnostics[0];
+    let mut message = format!(
+        "line {}, column {}: {}",
+        first.line, first.column, first.message
+    );
+    if err.diagnostics.len() > 1 {
+        message += &format!(" (and {} more)", err.diagnostics.len() - 1);
+    }
+    message
+}
+
+/// Error chain on a single line, for replies and events.
+fn one_line(err: &eyre::Report) -> String {
+    format!("{err:#}")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Seed for `random` without an explicit one; not reproducible, but it doesn't have to be.
+fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animations::IsFrame as _;
+
+    const DEVICE: &str = "/nonexistent/fw-lights-test-matrix";
+
+    /// State with a single display whose thread is only a channel.
+    fn old_state(error: Option<&str>) -> (State, mpsc::Receiver<DisplayCommand>) {
+        let (sender, receiver) = mpsc::channel();
+        let status = DisplayStatus {
+            brightness: 255,
+            error: error.map(str::to_owned),
+            ..DisplayStatus::default()
+        };
+        let display = Display {
+            config: toml::from_str::<Config>(&format!("displays = {{ left = \"{DEVICE}\" }}"))
+                .unwrap()
+                .displays
+                .remove("left")
+                .unwrap(),
+            sender,
+            status: Arc::new(Mutex::new(status)),
+        };
+        let state = State {
+            builtin: BuiltinConfig::default(),
+            animations: HashMap::new(),
+            animation_info: HashMap::new(),
+            displays: HashMap::from([("left".to_owned(), display)]),
+            socket_path: PathBuf::new(),
+            watch: false,
+            animation_files: Vec::new(),
+            animation_dirs: Vec::new(),
+        };
+        (state, receiver)
+    }
+
+    fn reload(old: &State, brightness: u8) -> eyre::Result<State> {
+        let config = toml::from_str(&format!(
+            "displays = {{ left = {{ path = \"{DEVICE}\", brightness = {brightness} }} }}"
+        ))?;
+        let (events, _) = mpsc::channel();
+        State::new(config, Some(old), &events)
+    }
+
+    #[test]
+    fn streamed_option_lines_are_refused() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let connection = Connection {
+            writer: Arc::new(Mutex::new(server.try_clone().unwrap())),
+            version: None,
+            subscribed: false,
+            streaming: None,
+        };
+        let (frames, received) = mpsc::channel();
+        let streaming = Streaming {
+            frames,
+            binary: false,
+        };
+        let rows = |row: &str| format!("{row}\n").repeat(34);
+        let sent = format!(
+            "{}\n{{ fullscreen = true }}\n{}",
+            rows("#........"),
+            rows(".#......."),
+        );
+        client.write_all(sent.as_bytes()).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+
+        receive_frames(&mut BufReader::new(server), &connection, &streaming).unwrap();
+        let lit: Vec<_> = received
+            .try_iter()
+            .map(|frame| match frame {
+                FrameData::Bw(frame) => (frame.get(0, 0), frame.get(1, 33)),
+                _ => panic!("rows of `#` and `.` should make black and white frames"),
+            })
+            .collect();
+        assert_eq!(lit, [(true, false), (false, true)]);
+        // the reply is all there is once the connection is closed
+        drop(connection);
+        let mut replies = String::new();
+        client.read_to_string(&mut replies).unwrap();
+        assert_eq!(replies, "ERR frame options can't be streamed\n");
+    }
+
+    #[test]
+    fn reload_reuses_running_displays() {
+        let (old, receiver) = old_state(None);
+        let new = reload(&old, 100).unwrap();
+        assert!(Arc::ptr_eq(
+            &new.displays["left"].status,
+            &old.displays["left"].status
+        ));
+        new.update_brightness(&old).unwrap();
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(DisplayCommand::SetBrightness(100))
+        ));
+    }
+
+    #[test]
+    fn reload_reopens_died_displays() {
+        let (old, _receiver) = old_state(Some("device unplugged"));
+        // the device isn't there, but opening it is attempted rather than keeping the dead thread
+        let err = reload(&old, 255).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("failed to open display `left` at `{DEVICE}`")
+        );
+    }
 }
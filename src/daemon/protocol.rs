@@ -0,0 +1,754 @@
+//! Control socket protocol.
+//!
+//! Every line is a command. Lines starting with `{` are JSON requests, anything else is
+//! the whitespace-separated text syntax answered with `OK ...` or `ERR <message>`.
+//! JSON requests are only accepted after a `hello` request negotiating the version.
+//!
+//! After `subscribe`, [`Event`]s are sent between replies: as JSON lines on connections
+//! which sent `hello`, as `EVENT <json>` lines otherwise.
+//!
+//! After `stream`, the client only sends frames; bad frames and the end of the stream
+//! are reported as errors without an `id`.
+//!
+//! In the text syntax, `define <name>` is followed by the animation file and a line `end`.
+
+use std::{fmt, str::FromStr as _, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Highest protocol version the daemon speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A JSON request line, e.g. `{"id": 1, "command": "play", "animation": "a", "display": "left"}`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RequestLine {
+    /// Echoed back in the reply, so requests can be matched with replies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub request: Request,
+}
+
+impl RequestLine {
+    /// Parses a JSON request line; on failure, the error reply keeps the `id` if there was one.
+    pub fn parse(line: &str) -> Result<Self, ReplyLine> {
+        let mut value = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(ReplyLine {
+                    id: None,
+                    reply: Reply::Error(Error::new(ErrorCode::BadRequest, err.to_string())),
+                });
+            }
+        };
+        // split manually, as `deny_unknown_fields` doesn't work with `flatten`
+        let id = value.as_object_mut().and_then(|object| object.remove("id"));
+        match Request::deserialize(value) {
+            Ok(request) => Ok(Self { id, request }),
+            Err(err) => {
+                let code = if err.to_string().starts_with("unknown variant") {
+                    ErrorCode::UnknownCommand
+                } else {
+                    ErrorCode::BadRequest
+                };
+                Err(ReplyLine {
+                    id,
+                    reply: Reply::Error(Error::new(code, err.to_string())),
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    /// Negotiates the protocol version; replies with the version the daemon will use.
+    Hello {
+        version: u32,
+    },
+    Charger,
+    Play {
+        animation: String,
+        display: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        offset: Option<i8>,
+        /// `[x, y, brightness]` replacing configured seeds.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seeds: Option<Vec<[u8; 3]>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        random: Option<RandomSeeds>,
+        /// Replies only once the animation is over, with how it ended and how long it ran.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        wait: bool,
+    },
+    /// The bar's `id` is sent as `bar` in JSON, where `id` identifies the request.
+    ProgressStart {
+        #[serde(rename = "bar")]
+        id: String,
+        display: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        style: Option<String>,
+    },
+    ProgressSet {
+        #[serde(rename = "bar")]
+        id: String,
+        value: u8,
+    },
+    ProgressDone {
+        #[serde(rename = "bar")]
+        id: String,
+    },
+    /// Removes every animation playing on a display.
+    Stop {
+        display: String,
+    },
+    Brightness {
+        display: String,
+        value: u8,
+    },
+    Reload,
+    ListDisplays,
+    ListAnimations,
+    /// What every display, or just the given one, is doing.
+    Status {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        display: Option<String>,
+    },
+    Version,
+    /// Starts sending events on this connection.
+    Subscribe,
+    /// Turns the connection into a live layer on the display: after the reply, the client
+    /// sends frames of 34 bare pixel rows in any row syntax of animation files, or 306
+    /// bytes each with `binary`, until it disconnects or doesn't send anything for
+    /// `timeout`. Frame options and directives aren't supported.
+    Stream {
+        display: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        binary: bool,
+        /// Defaults to 1s.
+        #[serde(
+            default,
+            with = "humantime_serde::option",
+            skip_serializing_if = "Option::is_none"
+        )]
+        timeout: Option<Duration>,
+    },
+    /// Registers an animation in the syntax of animation files, replacing one defined
+    /// before under the same name.
+    Define {
+        name: String,
+        body: String,
+    },
+    /// Forgets an animation registered by `define`.
+    Undefine {
+        name: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RandomSeeds {
+    /// At most [`MAX_RANDOM_SEEDS`].
+    pub count: usize,
+    /// Picked from the current time if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rng_seed: Option<u64>,
+}
+
+/// More seeds than LEDs would only land on the same ones again.
+pub const MAX_RANDOM_SEEDS: usize = 9 * 34;
+
+/// How an animation played with `wait` ended.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ending {
+    Finished,
+    /// Removed by `stop`.
+    Stopped,
+    DisplayDied,
+}
+
+/// Something that happened in the daemon, sent to subscribed connections.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// `id` identifies this playback, as returned by `play`.
+    AnimationStarted {
+        id: u64,
+        display: String,
+        animation: String,
+    },
+    /// `stopped` is set if the animation was stopped rather than having ended.
+    AnimationFinished {
+        id: u64,
+        display: String,
+        animation: String,
+        stopped: bool,
+    },
+    DisplayOnline {
+        display: String,
+    },
+    DisplayOffline {
+        display: String,
+        reason: String,
+    },
+    Charger {
+        throttled: bool,
+    },
+    Reloaded,
+    ReloadFailed {
+        message: String,
+    },
+}
+
+/// A JSON reply line: `{"id": 1, "ok": {...}}` or `{"id": 1, "error": {"code": ..., "message": ...}}`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplyLine {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub reply: Reply,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reply {
+    Ok(serde_json::Value),
+    Error(Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The line isn't a valid request.
+    BadRequest,
+    UnknownCommand,
+    /// JSON requests were sent before `hello`.
+    HandshakeRequired,
+    UnsupportedVersion,
+    BadArgs,
+    BadDisplay,
+    BadAnimation,
+    /// Seeds were passed to an animation which doesn't have any.
+    NotSeedable,
+    NoConfig,
+    BadStyle,
+    ProgressBarExists,
+    BadProgressBar,
+    ReloadFailed,
+    /// A streamed frame couldn't be parsed; the stream goes on.
+    BadFrame,
+    /// The stream layer was removed, because of its timeout or `stop`.
+    StreamEnded,
+    /// The body of `define` isn't a valid animation file.
+    BadDefinition,
+    /// Animations from the config can't be redefined or undefined.
+    AnimationConfigured,
+    Internal,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Error {
+    pub code: ErrorCode,
+    /// Human-readable description, also used as the text protocol reply.
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Whether a text line is followed by a body up to a line `end`. That's every `define`,
+/// even one with bad arguments, so the body of a rejected one isn't taken for commands.
+pub fn has_body(line: &str) -> bool {
+    line.split_ascii_whitespace().next() == Some("define")
+}
+
+/// Parses a text protocol line.
+pub fn parse_text(line: &str) -> Result<Request, Error> {
+    let words: Vec<_> = line.split_ascii_whitespace().collect();
+    Ok(match words.as_slice() {
+        ["charger"] => Request::Charger,
+        ["reload"] => Request::Reload,
+        ["list", "displays"] => Request::ListDisplays,
+        ["list", "animations"] => Request::ListAnimations,
+        ["status"] => Request::Status { display: None },
+        &["status", display] => Request::Status {
+            display: Some(display.to_owned()),
+        },
+        ["version"] => Request::Version,
+        ["subscribe"] => Request::Subscribe,
+        &["stream", "at", display, ref args @ ..] => {
+            let (binary, timeout) = parse_stream_args(args)
+                .map_err(|message| Error::new(ErrorCode::BadArgs, message))?;
+            Request::Stream {
+                display: display.to_owned(),
+                binary,
+                timeout,
+            }
+        }
+        // the body is read by the caller, as it spans several lines
+        &["define", name] => Request::Define {
+            name: name.to_owned(),
+            body: String::new(),
+        },
+        &["undefine", name] => Request::Undefine {
+            name: name.to_owned(),
+        },
+        &["play", animation, "at", display, ref args @ ..] => {
+            let (offset, seeds, random, wait) =
+                parse_play_args(args).map_err(|message| Error::new(ErrorCode::BadArgs, message))?;
+            Request::Play {
+                animation: animation.to_owned(),
+                display: display.to_owned(),
+                offset,
+                seeds,
+                random,
+                wait,
+            }
+        }
+        &["progress", "start", id, "at", display, ref args @ ..] => Request::ProgressStart {
+            id: id.to_owned(),
+            display: display.to_owned(),
+            style: match args {
+                [] => None,
+                ["style", style] => Some((*style).to_owned()),
+                _ => return Err(Error::new(ErrorCode::BadArgs, "bad args")),
+            },
+        },
+        &["stop", display] => Request::Stop {
+            display: display.to_owned(),
+        },
+        &["brightness", display, value] => Request::Brightness {
+            display: display.to_owned(),
+            value: u8::from_str(value).map_err(|_| Error::new(ErrorCode::BadArgs, "bad value"))?,
+        },
+        // `progress start 5` is a mistake rather than setting bar `start`
+        ["progress", "start" | "done", ..] => {
+            return Err(Error::new(ErrorCode::BadArgs, "bad args"));
+        }
+        &["progress", id, "done"] => Request::ProgressDone { id: id.to_owned() },
+        &["progress", id, value] => Request::ProgressSet {
+            id: id.to_owned(),
+            value: u8::from_str(value).map_err(|_| Error::new(ErrorCode::BadArgs, "bad value"))?,
+        },
+        _ => return Err(Error::new(ErrorCode::UnknownCommand, "unknown command")),
+    })
+}
+
+type PlayModifiers = (Option<i8>, Option<Vec<[u8; 3]>>, Option<RandomSeeds>, bool);
+
+/// Parses modifiers after `play <animation> at <display>`: `offset <n>`,
+/// `seed <x> <y> <brightness>` (repeatable), `random <count> [<rng seed>]` and `wait`.
+fn parse_play_args(mut args: &[&str]) -> Result<PlayModifiers, &'static str> {
+    let (mut offset, mut seeds, mut random, mut wait) = (None, None::<Vec<_>>, None, false);
+    loop {
+        match args {
+            [] => return Ok((offset, seeds, random, wait)),
+            ["wait", rest @ ..] => {
+                wait = true;
+                args = rest;
+            }
+            ["offset", value, rest @ ..] => {
+                let Ok(value) = i8::from_str(value) else {
+                    return Err("bad offset");
+                };
+                offset = Some(value);
+                args = rest;
+            }
+            ["seed", x, y, brightness, rest @ ..] => {
+                let (Ok(x), Ok(y), Ok(brightness)) =
+                    (u8::from_str(x), u8::from_str(y), u8::from_str(brightness))
+                else {
+                    return Err("bad seed");
+                };
+                seeds.get_or_insert_default().push([x, y, brightness]);
+                args = rest;
+            }
+            ["random", count, rest @ ..] => {
+                let Ok(count) = usize::from_str(count) else {
+                    return Err("bad seed count");
+                };
+                if count > MAX_RANDOM_SEEDS {
+                    return Err("bad seed count");
+                }
+                let explicit_seed = rest
+                    .split_first()
+                    .and_then(|(rng_seed, rest)| Some((u64::from_str(rng_seed).ok()?, rest)));
+                let (rng_seed, rest) = match explicit_seed {
+                    Some((rng_seed, rest)) => (Some(rng_seed), rest),
+                    None => (None, rest),
+                };
+                random = Some(RandomSeeds { count, rng_seed });
+                args = rest;
+            }
+            _ => return Err("bad args"),
+        }
+    }
+}
+
+/// Parses modifiers after `stream at <display>`: `binary` and `timeout <duration>`.
+fn parse_stream_args(mut args: &[&str]) -> Result<(bool, Option<Duration>), &'static str> {
+    let (mut binary, mut timeout) = (false, None);
+    loop {
+        match args {
+            [] => return Ok((binary, timeout)),
+            ["binary", rest @ ..] => {
+                binary = true;
+                args = rest;
+            }
+            ["timeout", value, rest @ ..] => {
+                let Ok(value) = humantime_serde::re::humantime::parse_duration(value) else {
+                    return Err("bad timeout");
+                };
+                timeout = Some(value);
+                args = rest;
+            }
+            _ => return Err("bad args"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_error(line: &str) -> ErrorCode {
+        parse_text(line).unwrap_err().code
+    }
+
+    #[test]
+    fn text_commands() {
+        assert!(matches!(parse_text("charger\n"), Ok(Request::Charger)));
+        assert!(matches!(parse_text("  reload  "), Ok(Request::Reload)));
+        assert!(matches!(
+            parse_text("play a at left offset 3"),
+            Ok(Request::Play {
+                offset: Some(3),
+                ..
+            })
+        ));
+        assert_eq!(text_error("play a at left offset 128"), ErrorCode::BadArgs);
+        assert!(matches!(
+            parse_text("brightness left 40"),
+            Ok(Request::Brightness { display, value: 40 }) if display == "left"
+        ));
+        assert!(matches!(
+            parse_text("stop left"),
+            Ok(Request::Stop { display }) if display == "left"
+        ));
+        assert_eq!(text_error("brightness left 256"), ErrorCode::BadArgs);
+        assert_eq!(text_error("dance"), ErrorCode::UnknownCommand);
+        assert_eq!(text_error(""), ErrorCode::UnknownCommand);
+    }
+
+    #[test]
+    fn json_requests() {
+        let request: Request = serde_json::from_str(
+            r#"{"command": "play", "animation": "a", "display": "left", "offset": -2}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            request,
+            Request::Play {
+                offset: Some(-2),
+                seeds: None,
+                random: None,
+                wait: false,
+                ..
+            }
+        ));
+        let request: Request =
+            serde_json::from_str(r#"{"command": "hello", "version": 1}"#).unwrap();
+        assert!(matches!(request, Request::Hello { version: 1 }));
+        assert!(
+            serde_json::from_str::<Request>(
+                r#"{"command": "stop", "display": "left", "all": true}"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn json_replies() {
+        let reply = ReplyLine {
+            id: Some(serde_json::json!("a")),
+            reply: Reply::Error(Error::new(ErrorCode::BadArgs, "bad args")),
+        };
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"id":"a","error":{"code":"bad_args","message":"bad args"}}"#
+        );
+        let reply = ReplyLine {
+            id: None,
+            reply: Reply::Ok(serde_json::json!({})),
+        };
+        assert_eq!(serde_json::to_string(&reply).unwrap(), r#"{"ok":{}}"#);
+        assert_eq!(
+            serde_json::to_value(ErrorCode::HandshakeRequired).unwrap(),
+            "handshake_required"
+        );
+    }
+
+    #[test]
+    fn json_progress() {
+        let line = RequestLine::parse(
+            r#"{"id": 7, "command": "progress_start", "bar": "build", "display": "left"}"#,
+        )
+        .unwrap();
+        assert_eq!(line.id, Some(serde_json::json!(7)));
+        assert!(matches!(
+            line.request,
+            Request::ProgressStart { id, display, style: None } if id == "build" && display == "left"
+        ));
+        let line = RequestLine::parse(
+            r#"{"id": 8, "command": "progress_set", "bar": "build", "value": 50}"#,
+        )
+        .unwrap();
+        assert_eq!(line.id, Some(serde_json::json!(8)));
+        assert!(matches!(
+            line.request,
+            Request::ProgressSet { id, value: 50 } if id == "build"
+        ));
+        let line = RequestLine::parse(r#"{"command": "progress_done", "bar": "build"}"#).unwrap();
+        assert_eq!(line.id, None);
+        assert!(matches!(line.request, Request::ProgressDone { id } if id == "build"));
+
+        // the bar isn't mistaken for the request ID
+        let Err(reply) = RequestLine::parse(r#"{"id": "build", "command": "progress_done"}"#)
+        else {
+            panic!("progress_done without a bar was accepted");
+        };
+        assert_eq!(reply.id, Some(serde_json::json!("build")));
+        assert!(matches!(
+            reply.reply,
+            Reply::Error(Error {
+                code: ErrorCode::BadRequest,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn json_request_errors() {
+        let Err(reply) = RequestLine::parse("{") else {
+            panic!("bad JSON was accepted");
+        };
+        assert!(reply.id.is_none());
+        assert!(matches!(
+            reply.reply,
+            Reply::Error(Error {
+                code: ErrorCode::BadRequest,
+                ..
+            })
+        ));
+        let Err(reply) = RequestLine::parse(r#"{"id": 1, "command": "dance"}"#) else {
+            panic!("unknown command was accepted");
+        };
+        assert_eq!(reply.id, Some(serde_json::json!(1)));
+        assert!(matches!(
+            reply.reply,
+            Reply::Error(Error {
+                code: ErrorCode::UnknownCommand,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn queries() {
+        assert!(matches!(
+            parse_text("list displays"),
+            Ok(Request::ListDisplays)
+        ));
+        assert!(matches!(
+            parse_text("list animations"),
+            Ok(Request::ListAnimations)
+        ));
+        assert!(matches!(parse_text("version"), Ok(Request::Version)));
+        assert!(matches!(
+            parse_text("status"),
+            Ok(Request::Status { display: None })
+        ));
+        assert!(matches!(
+            parse_text("status left"),
+            Ok(Request::Status { display: Some(display) }) if display == "left"
+        ));
+        assert_eq!(text_error("list"), ErrorCode::UnknownCommand);
+        assert_eq!(text_error("status left right"), ErrorCode::UnknownCommand);
+    }
+
+    #[test]
+    fn play_wait() {
+        assert!(matches!(
+            parse_text("play a at left wait"),
+            Ok(Request::Play { wait: true, .. })
+        ));
+        assert!(matches!(
+            parse_text("play a at left wait offset 2"),
+            Ok(Request::Play {
+                wait: true,
+                offset: Some(2),
+                ..
+            })
+        ));
+        let request: Request = serde_json::from_str(
+            r#"{"command": "play", "animation": "a", "display": "left", "wait": true}"#,
+        )
+        .unwrap();
+        assert!(matches!(request, Request::Play { wait: true, .. }));
+        assert_eq!(
+            serde_json::to_value(Ending::DisplayDied).unwrap(),
+            "display_died"
+        );
+    }
+
+    #[test]
+    fn stream() {
+        assert!(matches!(
+            parse_text("stream at left"),
+            Ok(Request::Stream {
+                binary: false,
+                timeout: None,
+                ..
+            })
+        ));
+        let Ok(Request::Stream {
+            display,
+            binary,
+            timeout,
+        }) = parse_text("stream at left timeout 500ms binary")
+        else {
+            panic!("stream wasn't parsed");
+        };
+        assert_eq!(display, "left");
+        assert!(binary);
+        assert_eq!(timeout, Some(Duration::from_millis(500)));
+        assert_eq!(parse_stream_args(&["timeout"]).unwrap_err(), "bad args");
+        assert_eq!(
+            parse_stream_args(&["timeout", "soon"]).unwrap_err(),
+            "bad timeout"
+        );
+        assert_eq!(text_error("stream left"), ErrorCode::UnknownCommand);
+
+        let request: Request =
+            serde_json::from_str(r#"{"command": "stream", "display": "left", "timeout": "2s"}"#)
+                .unwrap();
+        assert!(matches!(
+            request,
+            Request::Stream { timeout: Some(timeout), .. } if timeout == Duration::from_secs(2)
+        ));
+    }
+
+    #[test]
+    fn define() {
+        assert!(matches!(
+            parse_text("define pulse"),
+            Ok(Request::Define { name, body }) if name == "pulse" && body.is_empty()
+        ));
+        assert!(matches!(
+            parse_text("undefine pulse"),
+            Ok(Request::Undefine { name }) if name == "pulse"
+        ));
+        assert_eq!(text_error("define"), ErrorCode::UnknownCommand);
+        assert_eq!(text_error("define two words"), ErrorCode::UnknownCommand);
+        // bodies of rejected definitions are skipped too
+        assert!(has_body("define pulse\n"));
+        assert!(has_body("  define"));
+        assert!(has_body("define two words"));
+        assert!(!has_body("undefine pulse"));
+        assert!(!has_body("defined"));
+
+        let request: Request =
+            serde_json::from_str(r#"{"command": "define", "name": "pulse", "body": "---\n"}"#)
+                .unwrap();
+        assert!(matches!(request, Request::Define { body, .. } if body == "---\n"));
+        assert!(
+            serde_json::from_str::<Request>(r#"{"command": "define", "name": "pulse"}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn progress() {
+        assert!(matches!(
+            parse_text("progress start build at left style bar"),
+            Ok(Request::ProgressStart { id, display, style: Some(style) })
+                if id == "build" && display == "left" && style == "bar"
+        ));
+        assert!(matches!(
+            parse_text("progress build 50"),
+            Ok(Request::ProgressSet { id, value: 50 }) if id == "build"
+        ));
+        assert!(matches!(
+            parse_text("progress build done"),
+            Ok(Request::ProgressDone { id }) if id == "build"
+        ));
+        // a forgotten `at` rather than bars called `start` or `done`
+        assert_eq!(text_error("progress start 5"), ErrorCode::BadArgs);
+        assert_eq!(text_error("progress done 5"), ErrorCode::BadArgs);
+        assert_eq!(
+            text_error("progress start build at left style"),
+            ErrorCode::BadArgs
+        );
+        assert_eq!(text_error("progress build 300"), ErrorCode::BadArgs);
+    }
+
+    #[test]
+    fn play_seeds() {
+        let Ok(Request::Play {
+            offset,
+            seeds,
+            random,
+            wait,
+            ..
+        }) = parse_text("play spread at left offset -3 seed 1 2 255 seed 8 33 10")
+        else {
+            panic!("play wasn't parsed");
+        };
+        assert_eq!(offset, Some(-3));
+        assert_eq!(seeds, Some(vec![[1, 2, 255], [8, 33, 10]]));
+        assert!(random.is_none());
+        assert!(!wait);
+
+        let Ok((_, _, Some(random), _)) = parse_play_args(&["random", "5", "42"]) else {
+            panic!("random seeds weren't parsed");
+        };
+        assert_eq!((random.count, random.rng_seed), (5, Some(42)));
+        let Ok((_, _, Some(random), true)) = parse_play_args(&["random", "5", "wait"]) else {
+            panic!("random seeds weren't parsed");
+        };
+        assert_eq!((random.count, random.rng_seed), (5, None));
+
+        assert_eq!(
+            parse_play_args(&["seed", "1", "2"]).unwrap_err(),
+            "bad args"
+        );
+        assert_eq!(
+            parse_play_args(&["seed", "1", "2", "256"]).unwrap_err(),
+            "bad seed"
+        );
+        assert!(parse_play_args(&["random", "306"]).is_ok());
+        assert_eq!(
+            parse_play_args(&["random", "307"]).unwrap_err(),
+            "bad seed count"
+        );
+        assert_eq!(
+            text_error("play spread at left random -1"),
+            ErrorCode::BadArgs
+        );
+    }
+}
@@ -1,36 +1,130 @@
 use std::{
-    sync::mpsc,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, PoisonError, mpsc},
     thread::{self, JoinHandle},
     time::Instant,
 };
 
+use eyre::eyre;
+use serde::Serialize;
+
 use crate::{
     MatrixPort,
-    animations::Animation,
+    animations::{Animation, Frame},
     proto::{BwFrame, Command},
 };
 
 pub enum DisplayCommand {
     SetBrightness(u8),
-    AddAnimation(Animation),
+    /// Plays an animation; `id` and `name` are only used for status and events.
+    AddAnimation {
+        id: u64,
+        name: String,
+        animation: Animation,
+    },
+    /// Drops every playing animation.
+    Clear,
+}
+
+/// Reported by a display thread, identified by its device path.
+pub struct DisplayEvent {
+    pub device: String,
+    pub kind: DisplayEventKind,
+}
+
+pub enum DisplayEventKind {
+    AnimationStarted(PlayingAnimation),
+    /// `stopped` is set if it was cleared rather than having ended.
+    AnimationFinished {
+        animation: PlayingAnimation,
+        stopped: bool,
+    },
+    /// The thread has stopped because of an error.
+    Died {
+        error: String,
+    },
+}
+
+/// What a display thread is doing, kept up to date by the thread itself.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DisplayStatus {
+    pub brightness: u8,
+    /// Playing animations, from bottom to top.
+    pub playing: Vec<PlayingAnimation>,
+    /// Why the thread has died, if it has.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlayingAnimation {
+    pub id: u64,
+    pub name: String,
+}
+
+struct Playing {
+    info: PlayingAnimation,
+    animation: Animation,
+}
+
+impl Iterator for Playing {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        self.animation.next()
+    }
 }
 
 pub struct Matrix {
     port: MatrixPort,
+    device: String,
     brightness: u8,
-    animations: Vec<Animation>,
+    animations: Vec<Playing>,
+    status: Arc<Mutex<DisplayStatus>>,
+    events: mpsc::Sender<DisplayEvent>,
 }
 
 impl Matrix {
-    pub fn new(port: MatrixPort) -> eyre::Result<Self> {
+    pub fn new(
+        port: MatrixPort,
+        device: String,
+        events: mpsc::Sender<DisplayEvent>,
+    ) -> eyre::Result<Self> {
         let animations = Vec::with_capacity(16);
         Ok(Self {
             port,
+            device,
             animations,
             brightness: 255,
+            status: Arc::new(Mutex::new(DisplayStatus {
+                brightness: 255,
+                ..DisplayStatus::default()
+            })),
+            events,
         })
     }
 
+    /// Status shared with the thread once spawned.
+    pub fn status(&self) -> Arc<Mutex<DisplayStatus>> {
+        Arc::clone(&self.status)
+    }
+
+    fn report(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.brightness = self.brightness;
+        status.playing.clear();
+        status
+            .playing
+            .extend(self.animations.iter().map(|playing| playing.info.clone()));
+    }
+
+    fn send_event(&self, kind: DisplayEventKind) {
+        // the daemon outlives display threads, nobody to tell otherwise
+        let _ = self.events.send(DisplayEvent {
+            device: self.device.clone(),
+            kind,
+        });
+    }
+
     fn set_brightness(&mut self, brightness: u8) -> eyre::Result<()> {
         self.brightness = brightness;
         self.port
@@ -40,12 +134,27 @@ impl Matrix {
 
     fn process_command(&mut self, command: DisplayCommand) -> eyre::Result<()> {
         match command {
-            DisplayCommand::SetBrightness(brightness) => self.set_brightness(brightness),
-            DisplayCommand::AddAnimation(animation) => {
-                self.animations.push(animation);
-                Ok(())
+            DisplayCommand::SetBrightness(brightness) => self.set_brightness(brightness)?,
+            DisplayCommand::AddAnimation {
+                id,
+                name,
+                animation,
+            } => {
+                let info = PlayingAnimation { id, name };
+                self.send_event(DisplayEventKind::AnimationStarted(info.clone()));
+                self.animations.push(Playing { info, animation });
+            }
+            DisplayCommand::Clear => {
+                for playing in std::mem::take(&mut self.animations) {
+                    self.send_event(DisplayEventKind::AnimationFinished {
+                        animation: playing.info,
+                        stopped: true,
+                    });
+                }
             }
         }
+        self.report();
+        Ok(())
     }
 
     pub fn run(&mut self, rx: mpsc::Receiver<DisplayCommand>) -> eyre::Result<()> {
@@ -53,23 +162,29 @@ impl Matrix {
         self.set_brightness(self.brightness)?;
         let mut frames = Vec::with_capacity(16);
         loop {
-            if let Ok(command) = rx.try_recv() {
-                self.process_command(command)?
+            match rx.try_recv() {
+                Ok(command) => self.process_command(command)?,
+                // removed from the config, so nothing playing here will ever be seen
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    return self.process_command(DisplayCommand::Clear);
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
             }
 
-            self.animations.retain_mut(|animation| {
-                if let Some(frame) = animation.next() {
-                    frames.push(frame);
-                    true
-                } else {
-                    false
-                }
+            let playing = self.animations.len();
+            let frame = next_frame(&mut self.animations, &mut frames, self.brightness, |done| {
+                // can't borrow `self` as a whole while `animations` is borrowed
+                let _ = self.events.send(DisplayEvent {
+                    device: self.device.clone(),
+                    kind: DisplayEventKind::AnimationFinished {
+                        animation: done.info.clone(),
+                        stopped: false,
+                    },
+                });
             });
-
-            let frame = frames
-                .drain(..)
-                .reduce(|lower, upper| lower.merge(upper, self.brightness));
-
+            if self.animations.len() != playing {
+                self.report();
+            }
             let Some(frame) = frame else {
                 // draw an empty frame to reset display
                 self.port
@@ -89,6 +204,53 @@ impl Matrix {
 
     pub fn spawn(mut self) -> (mpsc::Sender<DisplayCommand>, JoinHandle<eyre::Result<()>>) {
         let (tx, rx) = mpsc::channel();
-        (tx, thread::spawn(move || self.run(rx)))
+        let handle = thread::spawn(move || {
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| self.run(rx))).unwrap_or_else(|payload| {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|message| (*message).to_owned())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    Err(eyre!("display thread panicked: {message}"))
+                });
+            if let Err(err) = &result {
+                let error = format!("{err:#}");
+                {
+                    // a panic may have happened while it was locked
+                    let mut status = self.status.lock().unwrap_or_else(PoisonError::into_inner);
+                    status.playing.clear();
+                    status.error = Some(error.clone());
+                }
+                self.send_event(DisplayEventKind::Died { error });
+            }
+            result
+        });
+        (tx, handle)
     }
 }
+
+/// Advances every animation by one frame and merges the results, dropping finished
+/// animations after passing them to `on_finished`.
+///
+/// `frames` is just a reusable buffer and is left empty.
+pub fn next_frame<A: Iterator<Item = Frame>>(
+    animations: &mut Vec<A>,
+    frames: &mut Vec<Frame>,
+    bw_brightness: u8,
+    mut on_finished: impl FnMut(&A),
+) -> Option<Frame> {
+    animations.retain_mut(|animation| {
+        if let Some(frame) = animation.next() {
+            frames.push(frame);
+            true
+        } else {
+            on_finished(animation);
+            false
+        }
+    });
+
+    frames
+        .drain(..)
+        .reduce(|lower, upper| lower.merge(upper, bw_brightness))
+}
@@ -0,0 +1,312 @@
+//! Rendering animations to image files instead of a display.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::Path,
+    thread,
+    time::Instant,
+};
+
+use eyre::{WrapErr as _, bail};
+use image::{
+    Delay, Rgba, RgbaImage,
+    codecs::gif::{GifEncoder, Repeat},
+};
+
+use crate::{
+    animations::{Animation, Frame, FrameData, GrayFrame},
+    display_thread,
+};
+
+pub struct ExportOptions {
+    /// Size of a single LED in image pixels.
+    pub scale: u32,
+    /// Display brightness; used the same way the display thread uses it.
+    pub brightness: u8,
+    /// Stop after this many frames, as some animations never end.
+    pub max_frames: usize,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            scale: 8,
+            brightness: 255,
+            max_frames: 1000,
+        }
+    }
+}
+
+/// Plays animations simultaneously, merging them exactly like a display would.
+pub fn render(mut animations: Vec<Animation>, options: &ExportOptions) -> Vec<Frame> {
+    let mut buffer = Vec::with_capacity(animations.len());
+    let mut result = Vec::new();
+    while result.len() < options.max_frames {
+        let Some(frame) =
+            display_thread::next_frame(&mut animations, &mut buffer, options.brightness, |_| {})
+        else {
+            break;
+        };
+        result.push(frame);
+    }
+    result
+}
+
+/// Writes frames as an animated GIF or a horizontal PNG sprite sheet, depending on extension.
+pub fn write(frames: &[Frame], path: &Path, options: &ExportOptions) -> eyre::Result<()> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("gif") => write_gif(frames, path, options),
+        Some("png") => write_sprite_sheet(frames, path, options),
+        _ => bail!(
+            "don't know how to export to `{}`: expected `.gif` or `.png`",
+            path.display()
+        ),
+    }
+}
+
+fn write_gif(frames: &[Frame], path: &Path, options: &ExportOptions) -> eyre::Result<()> {
+    let file =
+        File::create(path).wrap_err_with(|| format!("failed to create `{}`", path.display()))?;
+    let mut encoder = GifEncoder::new(BufWriter::new(file));
+    encoder.set_repeat(Repeat::Infinite)?;
+    let (width, height) = image_size(1, options)?;
+    for frame in frames {
+        let mut image = RgbaImage::new(width, height);
+        draw(&mut image, 0, frame, options);
+        encoder.encode_frame(image::Frame::from_parts(
+            image,
+            0,
+            0,
+            Delay::from_saturating_duration(frame.min_duration),
+        ))?;
+    }
+    Ok(())
+}
+
+fn write_sprite_sheet(frames: &[Frame], path: &Path, options: &ExportOptions) -> eyre::Result<()> {
+    let (width, _) = image_size(1, options)?;
+    let (sheet_width, height) = image_size(frames.len().max(1), options)?;
+    let mut image = RgbaImage::new(sheet_width, height);
+    for (idx, frame) in frames.iter().enumerate() {
+        draw(&mut image, idx as u32 * width, frame, options);
+    }
+    image
+        .save(path)
+        .wrap_err_with(|| format!("failed to write `{}`", path.display()))
+}
+
+/// Size of an image holding `frame_count` frames side by side.
+fn image_size(frame_count: usize, options: &ExportOptions) -> eyre::Result<(u32, u32)> {
+    let width = u32::try_from(frame_count)
+        .ok()
+        .and_then(|count| count.checked_mul(9))
+        .and_then(|width| width.checked_mul(options.scale));
+    let height = options.scale.checked_mul(34);
+    let (Some(width), Some(height)) = (width, height) else {
+        bail!("image would be too large, lower the scale or the frame count");
+    };
+    Ok((width, height))
+}
+
+/// Plays animations in the terminal in real time, two LED rows per line.
+pub fn preview(mut animations: Vec<Animation>, options: &ExportOptions) -> eyre::Result<()> {
+    let mut out = std::io::stdout().lock();
+    let mut buffer = Vec::with_capacity(animations.len());
+    for idx in 0..options.max_frames {
+        let Some(frame) =
+            display_thread::next_frame(&mut animations, &mut buffer, options.brightness, |_| {})
+        else {
+            break;
+        };
+        let before = Instant::now();
+
+        if idx != 0 {
+            // draw over the previous frame
+            write!(out, "\x1b[17A")?;
+        }
+        let pixels = visible_pixels(&frame, options.brightness);
+        for y in (0..34).step_by(2) {
+            for column in &pixels.0 {
+                let (top, bottom) = (column[y], column[y + 1]);
+                write!(
+                    out,
+                    "\x1b[38;2;{top};{top};{top}m\x1b[48;2;{bottom};{bottom};{bottom}m\u{2580}\u{2580}"
+                )?;
+            }
+            writeln!(out, "\x1b[0m")?;
+        }
+        out.flush()?;
+
+        thread::sleep(frame.min_duration.saturating_sub(before.elapsed()));
+    }
+    Ok(())
+}
+
+/// Pixels as they'd be seen on the display.
+fn visible_pixels(frame: &Frame, brightness: u8) -> GrayFrame {
+    let mut pixels = match &frame.data {
+        FrameData::Gray(frame) => frame.clone(),
+        // BW frames are drawn at full brightness and scaled by hardware
+        FrameData::Bw(frame) => GrayFrame::from_bw(frame.clone(), 255),
+        FrameData::Blend(frame) => frame.to_drawn(),
+    };
+    for value in pixels.0.iter_mut().flatten() {
+        // hardware brightness scales every pixel
+        // cast is safe, as the product is at most 255 * 255
+        *value = (u16::from(*value) * u16::from(brightness) / 255) as u8;
+    }
+    pixels
+}
+
+fn draw(image: &mut RgbaImage, left: u32, frame: &Frame, options: &ExportOptions) {
+    let pixels = visible_pixels(frame, options.brightness);
+    for (x, column) in pixels.0.iter().enumerate() {
+        for (y, &value) in column.iter().enumerate() {
+            for dx in 0..options.scale {
+                for dy in 0..options.scale {
+                    image.put_pixel(
+                        left + x as u32 * options.scale + dx,
+                        y as u32 * options.scale + dy,
+                        Rgba([value, value, value, 255]),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        animations::{IsFrame as _, file::Tween},
+        proto::BwFrame,
+    };
+
+    fn bw_frame(lit: bool) -> Frame {
+        let mut frame = BwFrame::default();
+        for x in 0..9 {
+            for y in 0..34 {
+                frame.set(x, y, lit);
+            }
+        }
+        Frame {
+            data: FrameData::Bw(frame),
+            min_duration: Duration::ZERO,
+            fullscreen: false,
+        }
+    }
+
+    fn gray_frame(x: usize, value: u8) -> Frame {
+        let mut frame = GrayFrame::default();
+        frame.0[x][0] = value;
+        Frame {
+            data: FrameData::Gray(frame),
+            min_duration: Duration::from_millis(20),
+            fullscreen: false,
+        }
+    }
+
+    #[test]
+    fn render_merges_animations() {
+        let mut dot = BwFrame::default();
+        dot.set(0, 0, true);
+        let bw = Frame {
+            data: FrameData::Bw(dot),
+            min_duration: Duration::from_millis(10),
+            fullscreen: false,
+        };
+        let animations: Vec<Animation> = vec![
+            Box::new(vec![bw.clone(), bw].into_iter()),
+            Box::new(std::iter::once(gray_frame(1, 100))),
+        ];
+        let frames = render(animations, &ExportOptions::default());
+        assert_eq!(frames.len(), 2);
+        let merged = visible_pixels(&frames[0], 255);
+        assert_eq!((merged.0[0][0], merged.0[1][0]), (255, 100));
+        assert!(matches!(frames[1].data, FrameData::Bw(_)));
+
+        let endless: Vec<Animation> = vec![Box::new(std::iter::repeat(gray_frame(0, 1)))];
+        let options = ExportOptions {
+            max_frames: 5,
+            ..ExportOptions::default()
+        };
+        assert_eq!(render(endless, &options).len(), 5);
+    }
+
+    #[test]
+    fn sprite_sheets_and_gifs() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sheet, gif) = (dir.path().join("sheet.png"), dir.path().join("anim.gif"));
+        let frames = [gray_frame(0, 255), gray_frame(8, 128)];
+        let options = ExportOptions {
+            scale: 2,
+            brightness: 128,
+            ..ExportOptions::default()
+        };
+        write(&frames, &sheet, &options).unwrap();
+        write(&frames, &gif, &options).unwrap();
+        let image = image::open(&sheet).unwrap().to_luma8();
+        let decoder =
+            image::codecs::gif::GifDecoder::new(std::io::BufReader::new(File::open(&gif).unwrap()));
+        let gif_frames = image::AnimationDecoder::into_frames(decoder.unwrap()).collect_frames();
+
+        // frames side by side, every LED a 2x2 square scaled by brightness
+        assert_eq!(image.dimensions(), (36, 68));
+        assert_eq!(image.get_pixel(1, 1).0, [128]);
+        assert_eq!(image.get_pixel(2, 0).0, [0]);
+        assert_eq!(image.get_pixel(18 + 16, 0).0, [64]);
+        assert_eq!(gif_frames.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn bad_outputs() {
+        let frames = [gray_frame(0, 255)];
+        let err = write(&frames, Path::new("out.bmp"), &ExportOptions::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "don't know how to export to `out.bmp`: expected `.gif` or `.png`"
+        );
+        let huge = ExportOptions {
+            scale: u32::MAX,
+            ..ExportOptions::default()
+        };
+        assert!(image_size(1, &huge).is_err());
+        assert_eq!(
+            image_size(3, &ExportOptions::default()).unwrap(),
+            (3 * 9 * 8, 34 * 8)
+        );
+    }
+
+    #[test]
+    fn bw_tweens_fade_into_keyframes() {
+        let (from, to) = (bw_frame(false), bw_frame(true));
+        let tween = Tween {
+            frames: 4,
+            easing: Default::default(),
+            positions: false,
+        };
+        let mut frames = vec![from.clone()];
+        frames.extend(tween.between(&from, &to));
+        frames.push(to);
+        // what a single animation puts on a display at brightness 100
+        let values: Vec<_> = render(
+            vec![Box::new(frames.into_iter())],
+            &ExportOptions {
+                brightness: 100,
+                ..ExportOptions::default()
+            },
+        )
+        .iter()
+        .map(|frame| visible_pixels(frame, 100).0[4][17])
+        .collect();
+        assert_eq!(values, [0, 20, 40, 60, 80, 100]);
+    }
+}
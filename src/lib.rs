@@ -11,10 +11,13 @@ use crate::{
 };
 
 pub mod animations;
+pub mod client;
 pub mod config;
+pub mod daemon;
 pub mod display_thread;
+pub mod export;
 pub mod proto;
-pub mod daemon;
+pub mod rng;
 
 pub struct MatrixPort {
     port: Box<dyn SerialPort>,
@@ -57,6 +60,7 @@ impl MatrixPort {
         match &frame.data {
             FrameData::Gray(gray_frame) => self.draw_gray_frame(gray_frame),
             FrameData::Bw(bw_frame) => self.send_command(Command::DrawBw(bw_frame)).map(drop),
+            FrameData::Blend(blend_frame) => self.draw_gray_frame(&blend_frame.to_drawn()),
         }
     }
 }
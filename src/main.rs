@@ -1,16 +1,334 @@
-use std::convert::Infallible;
+use std::{
+    io::Read as _,
+    path::{Path, PathBuf},
+    str::FromStr as _,
+};
 
-use eyre::eyre;
-use fw_lights::{config::Config, daemon};
+use clap::{Parser, Subcommand, ValueEnum};
+use eyre::{WrapErr as _, bail};
+use fw_lights::{
+    animations::{Animation, builder::AnimationBuilder, file::FileAnimation},
+    client::Client,
+    config::{self, Config},
+    daemon::{self, protocol},
+    export::{self, ExportOptions},
+};
+use tracing::{Level, level_filters::LevelFilter};
+use tracing_subscriber::EnvFilter;
 
-fn main() -> eyre::Result<Infallible> {
+#[derive(Parser)]
+#[command(version, about = "Framework LED Matrix daemon and tools")]
+struct Cli {
+    /// Most verbose level of logs to print; `RUST_LOG` directives take precedence.
+    #[arg(long, global = true, default_value = "info")]
+    log_level: Level,
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Full)]
+    log_format: LogFormat,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the daemon.
+    Daemon {
+        #[command(flatten)]
+        config: ConfigArg,
+    },
+    /// Parse and validate the config and every animation without touching hardware.
+    Check {
+        #[command(flatten)]
+        config: ConfigArg,
+    },
+    /// Print configured animations.
+    ListAnimations {
+        #[command(flatten)]
+        config: ConfigArg,
+    },
+    /// Play animations in the terminal.
+    Preview {
+        #[command(flatten)]
+        config: ConfigArg,
+        #[command(flatten)]
+        play: PlayArgs,
+    },
+    /// Render animations to a GIF or a PNG sprite sheet.
+    Export {
+        #[command(flatten)]
+        config: ConfigArg,
+        /// `.gif` or `.png` file to write.
+        output: PathBuf,
+        #[command(flatten)]
+        play: PlayArgs,
+        /// Size of a single LED in image pixels.
+        #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+        scale: u32,
+    },
+    /// Rewrite animation files in the canonical format.
+    Fmt {
+        /// Only report files which aren't formatted.
+        #[arg(long)]
+        check: bool,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Send a command to the running daemon, exiting with 1 if it refuses.
+    Ctl {
+        /// Socket to connect to, instead of the one from the config.
+        #[arg(long, conflicts_with = "config")]
+        socket: Option<PathBuf>,
+        /// Config to take the socket path from; if neither is set, the default config's socket
+        /// is used, or the default socket without one.
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Print replies as JSON.
+        #[arg(long)]
+        json: bool,
+        /// Command in the text syntax, e.g. `play spread at left`; `subscribe` prints
+        /// events as JSON lines until the daemon exits, `define <name>` reads the animation
+        /// from stdin.
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(clap::Args)]
+struct ConfigArg {
+    /// Defaults to `$XDG_CONFIG_HOME/fw-lights.toml`, then `/etc/fw-lights.toml`.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+impl ConfigArg {
+    fn path(&self) -> eyre::Result<PathBuf> {
+        match &self.config {
+            Some(path) => Ok(path.clone()),
+            None => config::find_default(),
+        }
+    }
+
+    fn load(&self) -> eyre::Result<Config> {
+        Config::load(&self.path()?)
+    }
+}
+
+#[derive(clap::Args)]
+struct PlayArgs {
+    /// Animations to play simultaneously, as `name` or `name@offset`.
+    #[arg(required = true)]
+    animations: Vec<String>,
+    /// Display brightness.
+    #[arg(long, default_value_t = 255)]
+    brightness: u8,
+    /// Stop after this many frames, as some animations never end.
+    #[arg(long, default_value_t = 1000)]
+    max_frames: usize,
+}
+
+impl PlayArgs {
+    fn options(&self) -> ExportOptions {
+        ExportOptions {
+            brightness: self.brightness,
+            max_frames: self.max_frames,
+            ..ExportOptions::default()
+        }
+    }
+}
+
+fn main() -> eyre::Result<()> {
     color_eyre::install()?;
-    tracing_subscriber::fmt::init();
-
-    let config_path = std::env::args()
-        .nth(1)
-        .ok_or_else(|| eyre!("please pass config path"))?;
-    let raw_config = std::fs::read_to_string(config_path)?;
-    let config: Config = toml::from_str(&raw_config)?;
-    daemon::run(config)
+    let cli = Cli::parse();
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::from_level(cli.log_level).into())
+        .from_env_lossy();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    match cli.log_format {
+        LogFormat::Full => subscriber.init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Pretty => subscriber.pretty().init(),
+    }
+
+    match cli.command {
+        Command::Daemon { config } => match daemon::run(&config.path()?)? {},
+        Command::Check { config } => check(&config),
+        Command::ListAnimations { config } => list_animations(&config),
+        Command::Preview { config, play } => {
+            let animations = build_animations(config.load()?, &play.animations)?;
+            export::preview(animations, &play.options())
+        }
+        Command::Export {
+            config,
+            output,
+            play,
+            scale,
+        } => {
+            let animations = build_animations(config.load()?, &play.animations)?;
+            let options = ExportOptions {
+                scale,
+                ..play.options()
+            };
+            let frames = export::render(animations, &options);
+            export::write(&frames, &output, &options)
+        }
+        Command::Fmt { check, files } => fmt(check, &files),
+        Command::Ctl {
+            socket,
+            config,
+            json,
+            command,
+        } => {
+            let socket_path = match (socket, config) {
+                (Some(socket), _) => socket,
+                (None, Some(config)) => Config::load(&config)?.socket_path,
+                (None, None) => match config::find_default() {
+                    Ok(config) => Config::load(&config)?.socket_path,
+                    // without a config the daemon can only be using the default socket
+                    Err(_) => config::default_socket_path(),
+                },
+            };
+            ctl(&socket_path, json, &command.join(" "))
+        }
+    }
+}
+
+fn check(config: &ConfigArg) -> eyre::Result<()> {
+    let path = config.path()?;
+    let mut config = Config::read(&path)?;
+    // missing displays are reported along with the rest of the config
+    let mut errors = config.validate().err().unwrap_or_default();
+    if let Err(displays) = config.validate_displays() {
+        errors.problems.extend(displays.problems);
+    }
+    if !errors.problems.is_empty() {
+        return Err(errors.into());
+    }
+    config.register_discovered();
+    let (animation_count, display_count) = (config.animations.len(), config.displays.len());
+
+    let mut animations: Vec<_> = config.animations.into_iter().collect();
+    animations.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut failed = 0;
+    for (name, animation) in animations {
+        if let Err(err) = AnimationBuilder::new(animation) {
+            eprintln!("animation `{name}` is broken: {err:#}");
+            failed += 1;
+        }
+    }
+    if failed != 0 {
+        bail!("{failed} of {animation_count} animations are broken");
+    }
+    println!(
+        "`{}` is valid: {animation_count} animations, {display_count} displays",
+        path.display()
+    );
+    Ok(())
+}
+
+fn list_animations(config: &ConfigArg) -> eyre::Result<()> {
+    let config = config.load()?;
+    let mut names: Vec<_> = config.animations.keys().collect();
+    names.sort();
+    for name in names {
+        let kind = config.animations[name].kind();
+        match config.descriptions.get(name) {
+            Some(description) => println!("{name}\t{kind}\t{description}"),
+            None => println!("{name}\t{kind}"),
+        }
+    }
+    Ok(())
+}
+
+/// Builds `name` or `name@offset` animations; unrelated broken animations don't matter.
+fn build_animations(mut config: Config, names: &[String]) -> eyre::Result<Vec<Animation>> {
+    let mut animations = Vec::new();
+    for animation in names {
+        let (name, offset) = match animation.split_once('@') {
+            Some((name, offset)) => (name, Some(i8::from_str(offset)?)),
+            None => (animation.as_str(), None),
+        };
+        let Some(animation_config) = config.animations.remove(name) else {
+            bail!("animation `{name}` does not exist or was specified twice");
+        };
+        let builder = AnimationBuilder::new(animation_config)
+            .wrap_err_with(|| format!("failed to load animation `{name}`"))?;
+        animations.push(match offset {
+            Some(offset) => builder.at(offset),
+            None => builder.build(),
+        });
+    }
+    Ok(animations)
+}
+
+fn ctl(socket_path: &Path, json: bool, command: &str) -> eyre::Result<()> {
+    let mut request = protocol::parse_text(command)?;
+    if let protocol::Request::Define { body, .. } = &mut request {
+        std::io::stdin()
+            .read_to_string(body)
+            .wrap_err("failed to read animation from stdin")?;
+    }
+    let subscribe = matches!(request, protocol::Request::Subscribe);
+    let mut client = Client::connect(socket_path)?;
+    match client.request(request)? {
+        Ok(result) if json => println!("{result}"),
+        Ok(result) if result.as_object().is_some_and(|object| object.is_empty()) => {
+            println!("OK")
+        }
+        Ok(result) => println!("OK {result}"),
+        Err(err) => {
+            if json {
+                println!("{}", serde_json::to_string(&err)?);
+            } else {
+                eprintln!("ERR {err}");
+            }
+            std::process::exit(1);
+        }
+    }
+    if subscribe {
+        // until the daemon goes away
+        loop {
+            let event = client.next_event()?;
+            println!("{}", serde_json::to_string(&event)?);
+        }
+    }
+    Ok(())
+}
+
+fn fmt(check: bool, paths: &[PathBuf]) -> eyre::Result<()> {
+    let mut unformatted = Vec::new();
+    for path in paths {
+        let raw = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read animation file `{}`", path.display()))?;
+        let animation = FileAnimation::load(path)?;
+        if animation.has_directives {
+            eprintln!(
+                "skipping `{}`: it uses names, labels, includes, sprites, tweens or compact \
+                 rows, which formatting would expand",
+                path.display()
+            );
+            continue;
+        }
+        let formatted = animation.write();
+        if formatted == raw {
+            continue;
+        }
+        if check {
+            unformatted.push(path.display().to_string());
+        } else {
+            std::fs::write(path, formatted)
+                .wrap_err_with(|| format!("failed to write animation file `{}`", path.display()))?;
+        }
+    }
+
+    if !unformatted.is_empty() {
+        bail!("not formatted: {}", unformatted.join(", "));
+    }
+    Ok(())
 }
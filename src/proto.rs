@@ -3,7 +3,7 @@ use smallvec::SmallVec;
 
 use crate::animations::IsFrame;
 
-#[derive(Clone, BinWrite)]
+#[derive(Clone, PartialEq, Eq, BinWrite)]
 #[bw(big)]
 pub struct BwFrame([u8; 39]);
 